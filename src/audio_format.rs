@@ -0,0 +1,169 @@
+use rodio::cpal::{SampleFormat, SampleRate, SupportedStreamConfig, SupportedStreamConfigRange};
+
+pub const PREFERRED_CHANNELS: u16 = 2;
+pub const PREFERRED_SAMPLE_RATE: u32 = 48_000;
+
+// prefers f32 stereo at 48 kHz, then anything f32 that can run at 48 kHz, then whatever the device defaults to
+pub fn pick_config(configs: impl IntoIterator<Item = SupportedStreamConfigRange>, default: SupportedStreamConfig) -> SupportedStreamConfig {
+    let candidates: Vec<SupportedStreamConfigRange> = configs
+        .into_iter()
+        .filter(|config| config.sample_format() == SampleFormat::F32)
+        .filter(|config| config.min_sample_rate().0 <= PREFERRED_SAMPLE_RATE && PREFERRED_SAMPLE_RATE <= config.max_sample_rate().0)
+        .collect();
+
+    candidates
+        .iter()
+        .find(|config| config.channels() == PREFERRED_CHANNELS)
+        .or_else(|| candidates.first())
+        .map(|config| config.with_sample_rate(SampleRate(PREFERRED_SAMPLE_RATE)))
+        .unwrap_or(default)
+}
+
+// the windows mic route moves f32 samples, so it takes any f32 config and leaves the rate and channels to FormatConverter
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+pub fn pick_f32_config(configs: impl IntoIterator<Item = SupportedStreamConfigRange>) -> Option<SupportedStreamConfig> {
+    let configs: Vec<SupportedStreamConfigRange> = configs.into_iter().filter(|config| config.sample_format() == SampleFormat::F32).collect();
+    let fallback = configs.iter().max_by_key(|config| (config.channels() == PREFERRED_CHANNELS, config.max_sample_rate()))?.with_max_sample_rate();
+    Some(pick_config(configs, fallback))
+}
+
+// interleaved samples from one channel layout to another, mono is spread to every channel and downmixing to mono averages
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))] // pulseaudio loopbacks resample on their own, only the windows mic route needs this
+pub fn mix_channels(samples: &[f32], input_channels: usize, output_channels: usize) -> Vec<f32> {
+    if input_channels == output_channels {
+        return samples.to_vec();
+    }
+
+    let mut mixed = Vec::with_capacity(samples.len() / input_channels * output_channels);
+    for frame in samples.chunks_exact(input_channels) {
+        if output_channels == 1 {
+            mixed.push(frame.iter().sum::<f32>() / input_channels as f32);
+        }
+        else {
+            mixed.extend((0..output_channels).map(|channel| frame[channel % input_channels]));
+        }
+    }
+    mixed
+}
+
+// streaming channel mix and linear resample, keeps the last frame around so chunk borders don't click
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))] // pulseaudio loopbacks resample on their own, only the windows mic route needs this
+pub struct FormatConverter {
+    input_channels: usize,
+    output_channels: usize,
+    step: f64, // input frames per output frame
+    position: f64, // where the next output frame lies, 0.0 is the last frame of the previous chunk
+    previous: Vec<f32>,
+}
+
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))] // pulseaudio loopbacks resample on their own, only the windows mic route needs this
+impl FormatConverter {
+    pub fn new(input_channels: u16, input_rate: u32, output_channels: u16, output_rate: u32) -> Self {
+        FormatConverter {
+            input_channels: input_channels as usize,
+            output_channels: output_channels as usize,
+            step: input_rate as f64 / output_rate as f64,
+            position: 1.0,
+            previous: vec![0.0; output_channels as usize],
+        }
+    }
+
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        let mixed = mix_channels(input, self.input_channels, self.output_channels);
+        let frames = mixed.len() / self.output_channels;
+        if frames == 0 {
+            return Vec::new();
+        }
+
+        let frame = |index: usize| if index == 0 { &self.previous[..] } else { &mixed[(index - 1) * self.output_channels..index * self.output_channels] };
+
+        let mut output = Vec::with_capacity(((frames as f64 / self.step) as usize + 1) * self.output_channels);
+        while self.position < frames as f64 {
+            let index = self.position as usize;
+            let fraction = (self.position - index as f64) as f32;
+            let (from, to) = (frame(index), frame(index + 1));
+            output.extend(from.iter().zip(to).map(|(from, to)| from + (to - from) * fraction));
+            self.position += self.step;
+        }
+
+        self.position -= frames as f64;
+        self.previous = mixed[(frames - 1) * self.output_channels..].to_vec();
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::cpal::SupportedBufferSize;
+
+    fn range(channels: u16, min: u32, max: u32, format: SampleFormat) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange::new(channels, SampleRate(min), SampleRate(max), SupportedBufferSize::Unknown, format)
+    }
+
+    #[test]
+    fn picks_stereo_48k_when_supported() {
+        let default = range(1, 44_100, 44_100, SampleFormat::I16).with_max_sample_rate();
+        let configs = [range(1, 8_000, 96_000, SampleFormat::F32), range(2, 8_000, 96_000, SampleFormat::F32)];
+
+        let config = pick_config(configs, default.clone());
+        assert_eq!((config.channels(), config.sample_rate().0), (2, 48_000));
+
+        let config = pick_config([range(1, 8_000, 96_000, SampleFormat::F32)], default.clone());
+        assert_eq!((config.channels(), config.sample_rate().0), (1, 48_000));
+
+        let config = pick_config([range(2, 44_100, 44_100, SampleFormat::F32)], default);
+        assert_eq!((config.channels(), config.sample_rate().0), (1, 44_100));
+    }
+
+    #[test]
+    fn only_picks_f32_configs_for_the_mic_route() {
+        let config = pick_f32_config([range(2, 48_000, 48_000, SampleFormat::I16), range(2, 44_100, 44_100, SampleFormat::F32)]).unwrap();
+        assert_eq!((config.sample_format(), config.sample_rate().0), (SampleFormat::F32, 44_100));
+
+        assert!(pick_f32_config([range(2, 48_000, 48_000, SampleFormat::I16)]).is_none());
+    }
+
+    #[test]
+    fn mixes_mono_and_stereo() {
+        assert_eq!(mix_channels(&[0.1, 0.2], 1, 2), vec![0.1, 0.1, 0.2, 0.2]);
+        assert_eq!(mix_channels(&[0.2, 0.4, -1.0, 1.0], 2, 1), vec![0.3_f32, 0.0]);
+        assert_eq!(mix_channels(&[0.1, 0.2, 0.3, 0.4], 4, 2), vec![0.1, 0.2]);
+    }
+
+    #[test]
+    fn passes_matching_formats_through() {
+        let mut converter = FormatConverter::new(2, 48_000, 2, 48_000);
+        let input: Vec<f32> = (0..20).map(|index| index as f32 / 20.0).collect();
+
+        let mut output = converter.process(&input[..10]);
+        output.extend(converter.process(&input[10..]));
+        // the last frame stays behind until the next chunk arrives
+        assert_eq!(output, input[..18]);
+    }
+
+    #[test]
+    fn resamples_44k_mono_to_48k_stereo() {
+        let mut converter = FormatConverter::new(1, 44_100, 2, 48_000);
+        let ramp: Vec<f32> = (0..4_410).map(|index| index as f32 / 4_410.0).collect();
+
+        let output: Vec<f32> = ramp.chunks(441).flat_map(|chunk| converter.process(chunk)).collect();
+        let frames = output.len() / 2;
+        assert!((4_798..=4_800).contains(&frames), "got {} frames", frames);
+
+        for (index, frame) in output.chunks_exact(2).enumerate() {
+            assert_eq!(frame[0], frame[1]);
+            let expected = index as f32 * (44_100.0 / 48_000.0) / 4_410.0;
+            assert!((frame[0] - expected).abs() < 1e-4, "frame {} is {} instead of {}", index, frame[0], expected);
+        }
+    }
+
+    #[test]
+    fn downsamples_without_drifting() {
+        let mut converter = FormatConverter::new(2, 48_000, 2, 44_100);
+        let silence = vec![0.0; 480 * 2];
+
+        let frames: usize = (0..100).map(|_| converter.process(&silence).len() / 2).sum();
+        assert!((44_099..=44_100).contains(&frames), "got {} frames", frames);
+    }
+}
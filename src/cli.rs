@@ -0,0 +1,330 @@
+use std::{fs, path::Path, sync::{Arc, atomic::{AtomicBool, Ordering}}, thread, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use crate::{AppState, JSONData, control::{ControlCommand, control_socket_name, runtime_directory, send_command}, SoundSystem, load_library, metadata::{filename, load_cached_sound_ids, lookup_sound_id, search_candidates}, new_app_state, play_sound, read_json_data, search::fuzzy_score, stats::PlayTrigger, stop_all_sounds, write_json_data};
+
+const STOP_ALL_FILE: &str = "soundboard-stop-all"; // in the runtime directory, headless players stop once it is newer than their start
+
+const USAGE: &str = "Usage: soundboard [command]
+
+Without a command the soundboard window opens.
+
+Commands:
+  play <name|path>     play a sound, in the open soundboard if there is one
+  stop-all             stop every playing sound
+  pause                pause the sounds in the open soundboard
+  resume               resume them again
+  volume <percent>     set the volume of the open soundboard
+  list [--tab <tab>]   list sounds, optionally only the ones in one tab
+  add-tab <dir>        add a folder as a new tab
+  setup-mic            create the virtual mic without opening the window
+  teardown-mic         remove the virtual mic again";
+
+pub fn run_cli(args: &[String]) -> i32 {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let result = match args.as_slice() {
+        ["play", query @ ..] if !query.is_empty() => play(&query.join(" ")),
+        ["stop-all"] => stop_all(),
+        ["pause"] => forward(ControlCommand::Pause),
+        ["resume"] => forward(ControlCommand::Resume),
+        ["volume", percent] => match percent.trim_end_matches('%').parse::<f32>() {
+            Ok(percent) => forward(ControlCommand::Volume { volume: percent / 100.0 }),
+            Err(_) => Err(format!("{} is not a volume in percent", percent)),
+        },
+        ["list"] => list(None),
+        ["list", "--tab", tab] => list(Some(tab)),
+        ["add-tab", directory] => add_tab_directory(directory),
+        ["setup-mic"] => setup_mic(),
+        ["teardown-mic"] => teardown_mic(),
+        ["help" | "--help" | "-h"] => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(USAGE.to_string()),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+// hands the command to the open soundboard, Ok(false) means there is none
+fn try_forward(command: &ControlCommand) -> Result<bool, String> {
+    let Ok(response) = send_command(&control_socket_name(), command) else {
+        return Ok(false);
+    };
+
+    if response.ok { Ok(true) } else { Err(response.error.unwrap_or_default()) }
+}
+
+fn forward(command: ControlCommand) -> Result<(), String> {
+    if try_forward(&command)? { Ok(()) } else { Err("The soundboard is not running.".to_string()) }
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_millis()).unwrap_or(0)
+}
+
+// a library path wins, then exact file or display names, otherwise a fuzzy match has to be close and clearly better than the rest
+// only sounds in the library can come out of this, the socket and the remote must not reach arbitrary files
+fn pick_sound(query: &str, sounds: &[(String, Vec<String>)]) -> Result<String, String> { // (file path, names it goes by)
+    if let Some((file_path, _)) = sounds.iter().find(|(file_path, _)| file_path == query) {
+        return Ok(file_path.clone());
+    }
+
+    let exact = sounds.iter().find(|(_, candidates)| {
+        candidates.iter().any(|candidate| {
+            let stem = Path::new(candidate).file_stem().unwrap_or_default().to_string_lossy();
+            candidate.eq_ignore_ascii_case(query) || stem.eq_ignore_ascii_case(query)
+        })
+    });
+    if let Some((file_path, _)) = exact {
+        return Ok(file_path.clone());
+    }
+
+    let mut matches: Vec<(i64, &String)> = sounds
+        .iter()
+        .filter_map(|(file_path, candidates)| Some((candidates.iter().filter_map(|candidate| fuzzy_score(query, candidate)).max()?, file_path)))
+        .collect();
+    matches.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+
+    let Some(&(best_score, best)) = matches.first() else {
+        return Err(format!("No sound matches {}", query));
+    };
+    // scattered matches lose a point per skipped character, so a real match scores at least one point per typed character
+    let minimum_score = query.chars().filter(|char| !char.is_whitespace()).count() as i64;
+    if best_score >= minimum_score && matches.get(1).is_none_or(|(score, _)| *score < best_score) {
+        return Ok(best.clone());
+    }
+
+    let closest: Vec<String> = matches.iter().take(5).map(|(_, file_path)| filename(file_path)).collect();
+    Err(format!("No sound clearly matches {}, the closest are: {}", query, closest.join(", ")))
+}
+
+// headless play runs as the user who typed the command, so it may also play a file that isn't in any tab
+fn pick_local_sound(query: &str, sounds: &[(String, Vec<String>)]) -> Result<String, String> {
+    pick_sound(query, sounds).or_else(|err| if Path::new(query).is_file() { Ok(query.to_string()) } else { Err(err) })
+}
+
+fn library_sounds(app_state: &AppState) -> Vec<(String, Vec<String>)> {
+    app_state
+        .loaded_files
+        .values()
+        .flatten()
+        .map(|file_path| (file_path.clone(), search_candidates(app_state, file_path)))
+        .collect()
+}
+
+pub fn resolve_sound(app_state: &AppState, query: &str) -> Result<String, String> {
+    pick_sound(query, &library_sounds(app_state))
+}
+
+#[allow(unused_variables)]
+fn headless_sound_system(json_data: &JSONData) -> Result<SoundSystem, String> {
+    // the window owns the virtual mic, so only play into one that already exists instead of loading the modules twice
+    #[cfg(target_os = "linux")]
+    {
+        if !crate::linux_lib::soundboard_sink_exists() {
+            return Err("The virtual mic is not set up, run `soundboard setup-mic` or open the soundboard first.".to_string());
+        }
+        return Ok(SoundSystem {
+            routing_mode: json_data.routing_mode,
+            ..SoundSystem::new(crate::linux_lib::open_soundboard_output())
+        });
+    }
+
+    #[allow(unreachable_code)]
+    Ok(crate::create_virtual_mic(json_data.routing_mode, json_data.latency))
+}
+
+fn play(query: &str) -> Result<(), String> {
+    match try_forward(&ControlCommand::Play { sound: query.to_string() }) {
+        Ok(true) => return Ok(()),
+        Ok(false) => {}
+        Err(_) if Path::new(query).is_file() => {} // the window only plays its library, other files are played from here
+        Err(err) => return Err(err),
+    }
+
+    // only the library and the cached ids, hashing everything or starting transcodes would outlive a single sound
+    let json_data = read_json_data().unwrap_or_default();
+    let mut app_state = new_app_state(headless_sound_system(&json_data)?);
+    app_state.loaded_files = load_library(&json_data.tabs);
+    app_state.json_data = json_data;
+    load_cached_sound_ids(&mut app_state);
+    app_state.play_history = crate::stats::load_play_history();
+
+    let file_path = pick_local_sound(query, &library_sounds(&app_state))?;
+    lookup_sound_id(&mut app_state, &file_path);
+    let started = now_millis();
+    play_sound(file_path.clone(), PlayTrigger::Cli, &mut app_state);
+    if app_state.currently_playing.is_empty() {
+        return Err(format!("Could not play {}", file_path)); // play_sound already said why
+    }
+    println!("Playing {}", file_path);
+
+    let stop_all_file = runtime_directory().join(STOP_ALL_FILE);
+    // Ctrl+C ends the wait instead of the process, so the play still gets recorded
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let interrupt = Arc::clone(&interrupted);
+    let _ = ctrlc::set_handler(move || interrupt.store(true, Ordering::SeqCst));
+
+    while !interrupted.load(Ordering::SeqCst) && app_state.currently_playing.iter().any(|playing_sound| !playing_sound.sink.empty()) {
+        let stopped_at = fs::read_to_string(&stop_all_file).ok().and_then(|content| content.trim().parse::<u128>().ok());
+        if stopped_at.is_some_and(|stopped_at| stopped_at >= started) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    stop_all_sounds(&mut app_state);
+    Ok(())
+}
+
+fn stop_all() -> Result<(), String> {
+    try_forward(&ControlCommand::Stop)?;
+
+    let stop_all_file = runtime_directory().join(STOP_ALL_FILE);
+    fs::write(&stop_all_file, now_millis().to_string()).map_err(|err| format!("Could not write {}: {}", stop_all_file.display(), err))
+}
+
+fn list(tab: Option<&str>) -> Result<(), String> {
+    let json_data = read_json_data().unwrap_or_default();
+    let library = load_library(&json_data.tabs);
+
+    let tabs: Vec<&String> = match tab {
+        Some(tab) => vec![json_data.tabs.iter().find(|known| known.as_str() == tab).ok_or(format!("{} is not a tab", tab))?],
+        None => json_data.tabs.iter().collect(),
+    };
+
+    let headers = tabs.len() > 1;
+    for tab in tabs {
+        if headers {
+            println!("[{}]", tab);
+        }
+        let mut sounds = library.get(tab).cloned().unwrap_or_default();
+        sounds.sort();
+        for file_path in sounds {
+            println!("{}\t{}", filename(&file_path), file_path);
+        }
+    }
+
+    Ok(())
+}
+
+fn add_tab_directory(directory: &str) -> Result<(), String> {
+    let directory = fs::canonicalize(directory).map_err(|err| format!("Could not find {}: {}", directory, err))?;
+    if !directory.is_dir() {
+        return Err(format!("{} is not a folder", directory.display()));
+    }
+
+    let Some(path) = directory.to_str() else {
+        return Err("Invalid path encoding!".to_string());
+    };
+
+    let mut json_data = read_json_data().unwrap_or_default();
+    if json_data.tabs.iter().any(|tab| tab == path) {
+        return Err(format!("{} is already a tab.", path));
+    }
+
+    json_data.tabs.push(path.to_string());
+    write_json_data(&json_data);
+    println!("Added {} as a new tab.", path);
+    Ok(())
+}
+
+fn setup_mic() -> Result<(), String> {
+    #[cfg(target_os = "linux")]
+    {
+        if crate::linux_lib::soundboard_sink_exists() {
+            println!("The virtual mic is already set up.");
+            return Ok(());
+        }
+
+        let json_data = read_json_data().unwrap_or_default();
+        let routing_mode = crate::linux_lib::setup_virtual_mic(json_data.routing_mode, json_data.latency.loopback_latency_msec);
+        println!("Set up the virtual mic using {}.", routing_mode.label());
+        return Ok(());
+    }
+
+    #[allow(unreachable_code)]
+    {
+        println!("VB Cable provides the virtual mic here, there is nothing to set up.");
+        Ok(())
+    }
+}
+
+fn teardown_mic() -> Result<(), String> {
+    #[cfg(target_os = "linux")]
+    {
+        crate::linux_lib::reload_sound(); // unloads every soundboard module
+        return Ok(());
+    }
+
+    #[allow(unreachable_code)]
+    {
+        println!("VB Cable provides the virtual mic here, there is nothing to remove.");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library() -> Vec<(String, Vec<String>)> {
+        [
+            ("/sounds/airhorn.mp3", vec!["airhorn.mp3", "", "loud"]),
+            ("/sounds/bruh.wav", vec!["bruh.wav", "Bruh moment"]),
+            ("/sounds/vine boom.mp3", vec!["vine boom.mp3"]),
+            ("/sounds/boom 1.mp3", vec!["boom 1.mp3"]),
+            ("/sounds/boom 2.mp3", vec!["boom 2.mp3"]),
+        ]
+        .into_iter()
+        .map(|(file_path, candidates)| (file_path.to_string(), candidates.into_iter().map(str::to_string).collect()))
+        .collect()
+    }
+
+    #[test]
+    fn only_library_paths_are_played() {
+        assert_eq!(pick_sound("/sounds/bruh.wav", &library()), Ok("/sounds/bruh.wav".to_string()));
+
+        let path = std::env::temp_dir().join(format!("soundboard-cli-{}-airhorn.mp3", std::process::id()));
+        fs::write(&path, "ID3").unwrap();
+        let outside = pick_sound(&path.to_string_lossy(), &library());
+        fs::remove_file(&path).unwrap();
+        assert!(outside.is_err());
+        assert!(pick_sound("/sounds/../etc/passwd", &library()).is_err());
+    }
+
+    #[test]
+    fn headless_play_also_takes_files_outside_the_library() {
+        let path = std::env::temp_dir().join(format!("soundboard-cli-{}-clip.mp3", std::process::id()));
+        fs::write(&path, "ID3").unwrap();
+        let path = path.to_string_lossy().to_string();
+
+        assert_eq!(pick_local_sound(&path, &library()), Ok(path.clone()));
+        assert_eq!(pick_local_sound("airhorn", &library()), Ok("/sounds/airhorn.mp3".to_string()));
+        fs::remove_file(&path).unwrap();
+        assert!(pick_local_sound(&path, &library()).is_err());
+    }
+
+    #[test]
+    fn exact_names_win_over_fuzzy_matches() {
+        assert_eq!(pick_sound("airhorn", &library()), Ok("/sounds/airhorn.mp3".to_string()));
+        assert_eq!(pick_sound("BRUH MOMENT", &library()), Ok("/sounds/bruh.wav".to_string()));
+        assert_eq!(pick_sound("loud", &library()), Ok("/sounds/airhorn.mp3".to_string()));
+        assert_eq!(pick_sound("boom 1", &library()), Ok("/sounds/boom 1.mp3".to_string()));
+    }
+
+    #[test]
+    fn fuzzy_matches_have_to_be_clear() {
+        assert_eq!(pick_sound("vine", &library()), Ok("/sounds/vine boom.mp3".to_string()));
+        assert!(pick_sound("boom", &library()).is_err()); // boom 1 and boom 2 match equally well
+        assert!(pick_sound("anp", &library()).is_err()); // only matches scattered over airhorn.mp3
+        assert_eq!(pick_sound("xyz", &library()), Err("No sound matches xyz".to_string()));
+    }
+}
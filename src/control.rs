@@ -0,0 +1,421 @@
+use std::{io::{self, BufRead, BufReader, Write}, path::PathBuf, sync::{Arc, Mutex, mpsc}, thread, time::Duration};
+
+use interprocess::local_socket::{Listener, ListenerOptions, Name, Stream, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{AppState, cli::resolve_sound, metadata::sound_display_name, play_sound, stats::PlayTrigger, stop_all_sounds, virtual_tabs::{VIRTUAL_TABS, tab_files}};
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5); // the window may be busy, but a client shouldn't hang forever
+
+// one JSON object per line in both directions, e.g. {"command":"play","sound":"airhorn"} -> {"ok":true}
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum ControlCommand {
+    Play { sound: String },
+    Stop,
+    Pause,
+    Resume,
+    Volume { volume: f32 }, // 1.0 is 100%
+    List {
+        #[serde(default)]
+        tab: Option<String>,
+    },
+    Tabs,
+    Status,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ListedSound {
+    pub tab: String,
+    pub name: String,
+    pub path: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NowPlaying {
+    pub path: String,
+    pub name: String,
+    pub position: f32,
+    pub length: f32,
+    pub paused: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ControlResponse {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sounds: Option<Vec<ListedSound>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tabs: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub playing: Option<Vec<NowPlaying>>,
+}
+
+impl ControlResponse {
+    pub fn ok() -> Self {
+        ControlResponse { ok: true, ..Default::default() }
+    }
+
+    pub fn error(message: String) -> Self {
+        ControlResponse { ok: false, error: Some(message), ..Default::default() }
+    }
+}
+
+// commands are handed to the bevy update loop, since only it may touch the AppState
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    pub reply: mpsc::Sender<ControlResponse>,
+}
+
+// per user and the same from any working directory, so every command finds the socket and the stop-all signal
+pub fn runtime_directory() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from).unwrap_or_else(std::env::temp_dir)
+}
+
+pub fn control_socket_name() -> String {
+    #[cfg(target_os = "windows")]
+    return "soundboard".to_string(); // \\.\pipe\soundboard
+
+    #[allow(unreachable_code)]
+    runtime_directory().join("soundboard.sock").to_string_lossy().to_string()
+}
+
+fn socket_name(name: &str) -> io::Result<Name<'_>> {
+    #[cfg(target_os = "windows")]
+    return name.to_ns_name::<interprocess::local_socket::GenericNamespaced>();
+
+    #[allow(unreachable_code)]
+    name.to_fs_name::<interprocess::local_socket::GenericFilePath>()
+}
+
+pub fn send_command(name: &str, command: &ControlCommand) -> io::Result<ControlResponse> {
+    let mut connection = BufReader::new(Stream::connect(socket_name(name)?)?);
+
+    let line = serde_json::to_string(command).expect("Could not convert control command to JSON");
+    connection.get_mut().write_all(format!("{}\n", line).as_bytes())?;
+
+    let mut response = String::new();
+    connection.read_line(&mut response)?;
+    serde_json::from_str(&response).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+pub fn instance_running(name: &str) -> bool {
+    socket_name(name).and_then(Stream::connect).is_ok()
+}
+
+// queues the command for the update loop and waits for its answer
+pub fn submit(requests: &Arc<Mutex<Vec<ControlRequest>>>, command: ControlCommand) -> ControlResponse {
+    let (reply, replies) = mpsc::channel();
+    requests.lock().expect("Control requests lock poisoned").push(ControlRequest { command, reply });
+    replies.recv_timeout(REPLY_TIMEOUT).unwrap_or_else(|_| ControlResponse::error("The soundboard did not answer in time.".to_string()))
+}
+
+fn handle_connection(connection: Stream, requests: Arc<Mutex<Vec<ControlRequest>>>) {
+    let mut connection = BufReader::new(connection);
+    let mut line = String::new();
+
+    while connection.read_line(&mut line).is_ok_and(|read| read > 0) {
+        if line.trim().is_empty() {
+            line.clear();
+            continue;
+        }
+
+        let response = match serde_json::from_str::<ControlCommand>(&line) {
+            Ok(command) => submit(&requests, command),
+            Err(err) => ControlResponse::error(format!("Invalid command: {}", err)),
+        };
+        line.clear();
+
+        let response = serde_json::to_string(&response).expect("Could not convert control response to JSON");
+        if connection.get_mut().write_all(format!("{}\n", response).as_bytes()).is_err() {
+            break;
+        }
+    }
+}
+
+// binding is the single instance check, a second window fails here with AddrInUse before it loads the pactl modules
+pub fn bind_control_socket(name: &str) -> io::Result<Listener> {
+    match ListenerOptions::new().name(socket_name(name)?).create_sync() {
+        // nobody answers on a socket left behind by a crash, so it is replaced
+        Err(err) if err.kind() == io::ErrorKind::AddrInUse && !instance_running(name) => {
+            ListenerOptions::new().name(socket_name(name)?).try_overwrite(true).create_sync()
+        }
+        result => result,
+    }
+}
+
+pub fn start_control_server(listener: Listener, requests: &Arc<Mutex<Vec<ControlRequest>>>) {
+    let requests = Arc::clone(requests);
+    thread::spawn(move || {
+        for connection in listener.incoming().filter_map(Result::ok) {
+            let requests = Arc::clone(&requests);
+            thread::spawn(move || handle_connection(connection, requests));
+        }
+    });
+}
+
+fn handle_command(app_state: &mut AppState, command: ControlCommand) -> ControlResponse {
+    match command {
+        ControlCommand::Play { sound } => {
+            let file_path = match resolve_sound(app_state, &sound) {
+                Ok(file_path) => file_path,
+                Err(err) => return ControlResponse::error(err),
+            };
+
+            let playing = app_state.currently_playing.len();
+            play_sound(file_path.clone(), PlayTrigger::Remote, app_state);
+            if app_state.currently_playing.len() > playing {
+                ControlResponse::ok()
+            }
+            else {
+                ControlResponse::error(format!("Could not play {}", file_path))
+            }
+        }
+        ControlCommand::Stop => {
+            stop_all_sounds(app_state);
+            ControlResponse::ok()
+        }
+        ControlCommand::Pause => {
+            app_state.currently_playing.iter().for_each(|playing_sound| playing_sound.sink.pause());
+            ControlResponse::ok()
+        }
+        ControlCommand::Resume => {
+            app_state.currently_playing.iter().for_each(|playing_sound| playing_sound.sink.play());
+            ControlResponse::ok()
+        }
+        ControlCommand::Volume { volume } => {
+            app_state.volume = volume.clamp(0.0, 2.0);
+            for playing_sound in &app_state.currently_playing {
+                playing_sound.sink.set_volume(app_state.volume);
+                #[cfg(target_os = "windows")]
+                playing_sound.normal_sink.set_volume(app_state.volume);
+            }
+            ControlResponse::ok()
+        }
+        ControlCommand::List { tab } => {
+            let tabs = match tab {
+                Some(tab) => vec![tab],
+                None => app_state.json_data.tabs.clone(),
+            };
+
+            let sounds = tabs
+                .iter()
+                .flat_map(|tab| {
+                    tab_files(app_state, tab).into_iter().map(|path| ListedSound { tab: tab.clone(), name: sound_display_name(app_state, &path), path })
+                })
+                .collect();
+            ControlResponse { sounds: Some(sounds), ..ControlResponse::ok() }
+        }
+        ControlCommand::Tabs => {
+            let tabs = VIRTUAL_TABS.iter().map(|tab| tab.to_string()).chain(app_state.json_data.tabs.iter().cloned()).collect();
+            ControlResponse { tabs: Some(tabs), ..ControlResponse::ok() }
+        }
+        ControlCommand::Status => {
+            let playing = app_state
+                .currently_playing
+                .iter()
+                .map(|playing_sound| NowPlaying {
+                    path: playing_sound.file_path.clone(),
+                    name: sound_display_name(app_state, &playing_sound.file_path),
+                    position: playing_sound.sink.get_pos().as_secs_f32(),
+                    length: playing_sound.length,
+                    paused: playing_sound.sink.is_paused(),
+                })
+                .collect();
+            ControlResponse { playing: Some(playing), ..ControlResponse::ok() }
+        }
+    }
+}
+
+pub fn poll_control_requests(app_state: &mut AppState) {
+    let requests = std::mem::take(&mut *app_state.control_requests.lock().expect("Control requests lock poisoned"));
+
+    for request in requests {
+        let response = handle_command(app_state, request.command);
+        let _ = request.reply.send(response); // the client may have given up already
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{answer_requests, temp_dir};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn unique_id() -> usize {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    }
+
+    fn test_socket_name() -> String {
+        let file_name = format!("soundboard-test-{}-{}.sock", std::process::id(), unique_id());
+        if cfg!(target_os = "windows") { file_name } else { std::env::temp_dir().join(file_name).to_string_lossy().to_string() }
+    }
+
+    #[test]
+    fn parses_line_delimited_commands() {
+        assert_eq!(serde_json::from_str::<ControlCommand>(r#"{"command":"play","sound":"airhorn"}"#).unwrap(), ControlCommand::Play { sound: "airhorn".to_string() });
+        assert_eq!(serde_json::from_str::<ControlCommand>(r#"{"command":"list"}"#).unwrap(), ControlCommand::List { tab: None });
+        assert_eq!(serde_json::from_str::<ControlCommand>(r#"{"command":"volume","volume":0.5}"#).unwrap(), ControlCommand::Volume { volume: 0.5 });
+        assert_eq!(serde_json::to_string(&ControlCommand::Stop).unwrap(), r#"{"command":"stop"}"#);
+        assert_eq!(serde_json::to_string(&ControlResponse::ok()).unwrap(), r#"{"ok":true}"#);
+    }
+
+    #[test]
+    fn forwards_commands_to_the_running_instance() {
+        let name = test_socket_name();
+        assert!(!instance_running(&name));
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        start_control_server(bind_control_socket(&name).unwrap(), &requests);
+        let received = answer_requests(Arc::clone(&requests));
+        assert!(instance_running(&name));
+
+        assert_eq!(send_command(&name, &ControlCommand::Play { sound: "airhorn".to_string() }).unwrap(), ControlResponse::ok());
+        assert_eq!(send_command(&name, &ControlCommand::Play { sound: "missing".to_string() }).unwrap(), ControlResponse::error("No sound matches missing".to_string()));
+        assert_eq!(send_command(&name, &ControlCommand::Volume { volume: 0.5 }).unwrap(), ControlResponse::ok());
+
+        let listed = send_command(&name, &ControlCommand::List { tab: None }).unwrap();
+        assert_eq!(listed.sounds.unwrap()[0].path, "/sounds/airhorn.mp3");
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![
+                ControlCommand::Play { sound: "airhorn".to_string() },
+                ControlCommand::Play { sound: "missing".to_string() },
+                ControlCommand::Volume { volume: 0.5 },
+                ControlCommand::List { tab: None },
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_connections_open_and_rejects_bad_lines() {
+        let name = test_socket_name();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        start_control_server(bind_control_socket(&name).unwrap(), &requests);
+        answer_requests(Arc::clone(&requests));
+
+        let mut connection = BufReader::new(Stream::connect(socket_name(&name).unwrap()).unwrap());
+        connection.get_mut().write_all(b"not json\n\n{\"command\":\"pause\"}\n{\"command\":\"resume\"}\n").unwrap();
+
+        let mut responses = Vec::new();
+        for _ in 0..3 {
+            let mut line = String::new();
+            connection.read_line(&mut line).unwrap();
+            responses.push(serde_json::from_str::<ControlResponse>(&line).unwrap());
+        }
+
+        assert!(!responses[0].ok);
+        assert!(responses[0].error.as_ref().unwrap().starts_with("Invalid command"));
+        assert_eq!(responses[1..], [ControlResponse::ok(), ControlResponse::ok()]);
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn a_second_instance_cannot_bind_a_live_socket() {
+        let name = test_socket_name();
+        start_control_server(bind_control_socket(&name).unwrap(), &Arc::new(Mutex::new(Vec::new())));
+
+        assert_eq!(bind_control_socket(&name).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+    }
+
+    // a second of 16 bit stereo silence at 48 kHz
+    fn write_wav(path: &std::path::Path) {
+        let data_len: u32 = 48_000 * 4;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes()); // pcm
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&48_000u32.to_le_bytes());
+        wav.extend_from_slice(&(48_000u32 * 4).to_le_bytes());
+        wav.extend_from_slice(&4u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        wav.resize(wav.len() + data_len as usize, 0);
+        std::fs::write(path, wav).unwrap();
+    }
+
+    // an AppState with one tab holding airhorn.wav, playing into a mixer nothing listens to
+    fn test_app_state() -> (AppState, String, String) {
+        let tab = temp_dir(&format!("control-{}", unique_id()));
+        let sound = tab.join("airhorn.wav");
+        write_wav(&sound);
+        let (tab, sound) = (tab.to_string_lossy().to_string(), sound.to_string_lossy().to_string());
+
+        let mut app_state = crate::new_app_state(crate::SoundSystem::null());
+        app_state.json_data.tabs = vec![tab.clone()];
+        app_state.loaded_files.insert(tab.clone(), vec![sound.clone()]);
+        app_state.json_data.recent = vec![sound.clone()]; // already first, so playing doesn't write data.json
+        (app_state, tab, sound)
+    }
+
+    // goes through the queue and poll_control_requests like a socket client would
+    fn request(app_state: &mut AppState, command: ControlCommand) -> ControlResponse {
+        let (reply, response) = mpsc::channel();
+        app_state.control_requests.lock().unwrap().push(ControlRequest { command, reply });
+        poll_control_requests(app_state);
+        response.try_recv().unwrap()
+    }
+
+    #[test]
+    fn plays_pauses_and_resumes_library_sounds() {
+        let (mut app_state, _, sound) = test_app_state();
+
+        assert_eq!(request(&mut app_state, ControlCommand::Play { sound: "airhorn".to_string() }), ControlResponse::ok());
+        assert_eq!(app_state.currently_playing.len(), 1);
+        assert_eq!(app_state.currently_playing[0].file_path, sound);
+
+        let missing = request(&mut app_state, ControlCommand::Play { sound: "trombone".to_string() });
+        assert_eq!(missing, ControlResponse::error("No sound matches trombone".to_string()));
+
+        // a real file that isn't in any tab stays out of reach
+        let outside = std::path::Path::new(&sound).with_extension("outside.wav");
+        write_wav(&outside);
+        assert!(!request(&mut app_state, ControlCommand::Play { sound: outside.to_string_lossy().to_string() }).ok);
+        assert_eq!(app_state.currently_playing.len(), 1);
+
+        assert_eq!(request(&mut app_state, ControlCommand::Pause), ControlResponse::ok());
+        let status = request(&mut app_state, ControlCommand::Status).playing.unwrap();
+        assert_eq!((status[0].path.as_str(), status[0].paused), (sound.as_str(), true));
+
+        assert_eq!(request(&mut app_state, ControlCommand::Resume), ControlResponse::ok());
+        assert!(!request(&mut app_state, ControlCommand::Status).playing.unwrap()[0].paused);
+
+        assert_eq!(request(&mut app_state, ControlCommand::Volume { volume: 5.0 }), ControlResponse::ok());
+        assert_eq!(app_state.volume, 2.0);
+        assert_eq!(app_state.currently_playing[0].sink.volume(), 2.0);
+    }
+
+    #[test]
+    fn lists_tabs_and_their_sounds() {
+        let (mut app_state, tab, sound) = test_app_state();
+
+        let listed = request(&mut app_state, ControlCommand::List { tab: Some(tab.clone()) }).sounds.unwrap();
+        assert_eq!(listed, vec![ListedSound { tab: tab.clone(), name: "airhorn.wav".to_string(), path: sound.clone() }]);
+        assert_eq!(request(&mut app_state, ControlCommand::List { tab: None }).sounds.unwrap(), listed);
+
+        let tabs = request(&mut app_state, ControlCommand::Tabs).tabs.unwrap();
+        assert_eq!(tabs.last(), Some(&tab));
+        assert!(VIRTUAL_TABS.iter().all(|virtual_tab| tabs.contains(&virtual_tab.to_string())));
+    }
+
+    #[test]
+    fn replaces_sockets_left_behind_by_a_crash() {
+        let name = test_socket_name();
+        if !cfg!(target_os = "windows") {
+            std::fs::write(&name, "").unwrap(); // a plain file where the socket should be, like after a crash
+        }
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        start_control_server(bind_control_socket(&name).unwrap(), &requests);
+        answer_requests(Arc::clone(&requests));
+        assert_eq!(send_command(&name, &ControlCommand::Stop).unwrap(), ControlResponse::ok());
+    }
+}
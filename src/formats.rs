@@ -0,0 +1,191 @@
+use std::{collections::{HashSet, VecDeque}, fs::{self, File}, io::Read, path::{Path, PathBuf}, process::Command, sync::{Arc, Mutex}, thread};
+
+use crate::{AppState, TRANSCODE_FILE_EXTENSIONS, metadata::sound_id, yt_dlp::check_ffmpeg};
+
+pub const TRANSCODE_CACHE_DIRECTORY: &str = "cache/transcoded";
+const TRANSCODE_WORKERS: usize = 2;
+
+// generic mp4 brands stay allowed since .mp4 files with an audio track play fine, images like heic or avif share the container
+const MP4_AUDIO_BRANDS: [&[u8; 4]; 8] = [b"M4A ", b"M4B ", b"M4P ", b"mp41", b"mp42", b"isom", b"iso2", b"dash"];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AudioFormat {
+    Mp3,
+    Wav,
+    Flac,
+    Vorbis,
+    Mp4,
+    Aac,
+    Aiff,
+    Opus,
+    Matroska,
+    Other, // something only ffmpeg understands, recognised by extension
+}
+
+impl AudioFormat {
+    pub fn is_native(self) -> bool { // whether rodio can decode it without transcoding
+        !matches!(self, AudioFormat::Opus | AudioFormat::Matroska | AudioFormat::Other)
+    }
+}
+
+fn sniff_format(header: &[u8]) -> Option<AudioFormat> {
+    if header.starts_with(b"ID3") {
+        return Some(AudioFormat::Mp3);
+    }
+    if header.len() >= 12 && &header[0..4] == b"RIFF" && &header[8..12] == b"WAVE" {
+        return Some(AudioFormat::Wav);
+    }
+    if header.starts_with(b"fLaC") {
+        return Some(AudioFormat::Flac);
+    }
+    if header.starts_with(b"OggS") {
+        // opus and vorbis share the ogg container, the codec header is in the first page
+        let is_opus = header.windows(8).any(|window| window == b"OpusHead");
+        return Some(if is_opus { AudioFormat::Opus } else { AudioFormat::Vorbis });
+    }
+    if header.len() >= 12 && &header[4..8] == b"ftyp" {
+        let is_audio = MP4_AUDIO_BRANDS.iter().any(|brand| &header[8..12] == *brand);
+        return is_audio.then_some(AudioFormat::Mp4);
+    }
+    if header.len() >= 12 && &header[0..4] == b"FORM" && (&header[8..12] == b"AIFF" || &header[8..12] == b"AIFC") {
+        return Some(AudioFormat::Aiff);
+    }
+    if header.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        return Some(AudioFormat::Matroska);
+    }
+    if header.len() >= 2 && header[0] == 0xFF && header[1] & 0xF0 == 0xF0 && header[1] & 0x06 == 0 {
+        return Some(AudioFormat::Aac); // ADTS frame sync, layer bits are always 0
+    }
+    if header.len() >= 2 && header[0] == 0xFF && header[1] & 0xE0 == 0xE0 {
+        return Some(AudioFormat::Mp3); // MPEG frame sync without an ID3 tag
+    }
+
+    None
+}
+
+fn format_from_extension(path: &Path) -> Option<AudioFormat> {
+    let extension = path.extension()?.to_string_lossy().to_lowercase();
+
+    match extension.as_str() {
+        "mp3" => Some(AudioFormat::Mp3),
+        "wav" => Some(AudioFormat::Wav),
+        "flac" => Some(AudioFormat::Flac),
+        "ogg" | "oga" => Some(AudioFormat::Vorbis),
+        "m4a" | "mp4" => Some(AudioFormat::Mp4),
+        "aac" => Some(AudioFormat::Aac),
+        "aif" | "aiff" => Some(AudioFormat::Aiff),
+        "opus" => Some(AudioFormat::Opus),
+        "webm" | "mka" | "mkv" => Some(AudioFormat::Matroska),
+        _ if TRANSCODE_FILE_EXTENSIONS.contains(&extension.as_str()) => Some(AudioFormat::Other),
+        _ => None,
+    }
+}
+
+pub fn detect_format(path: &Path) -> Option<AudioFormat> {
+    let mut header = [0u8; 64];
+    let read = File::open(path).and_then(|mut file| file.read(&mut header)).unwrap_or(0);
+
+    // the extension alone can't be trusted (.ogg may be opus, .mp3 may be a renamed m4a), so look at the content first
+    sniff_format(&header[..read]).or_else(|| format_from_extension(path))
+}
+
+pub fn transcoded_path(sound_id: &str) -> PathBuf {
+    Path::new(TRANSCODE_CACHE_DIRECTORY).join(format!("{}.flac", sound_id))
+}
+
+pub fn playable_path(app_state: &AppState, file_path: &str) -> Option<String> {
+    match detect_format(Path::new(file_path)) {
+        Some(format) if format.is_native() => Some(file_path.to_string()),
+        _ => {
+            let cached = transcoded_path(&sound_id(app_state, file_path));
+            cached.exists().then(|| cached.to_string_lossy().to_string())
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Transcoding {
+    pending: VecDeque<(String, PathBuf)>,
+    active: HashSet<String>,
+    workers: usize,
+    failed: HashSet<PathBuf>, // not retried until the next start, ffmpeg would only fail again
+}
+
+impl Transcoding {
+    pub fn is_converting(&self, file_path: &str) -> bool {
+        self.active.contains(file_path) || self.pending.iter().any(|(pending, _)| pending == file_path)
+    }
+
+    pub fn has_failed(&self, sound_id: &str) -> bool {
+        self.failed.contains(&transcoded_path(sound_id))
+    }
+}
+
+pub fn transcode(source: &str, destination: &Path) -> bool {
+    let partial = destination.with_extension("part.flac"); // renamed once done, so a half written file is never played
+    let converted = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-i", source, "-vn", "-c:a", "flac"])
+        .arg(&partial)
+        .output()
+        .is_ok_and(|output| output.status.success());
+
+    if converted && fs::rename(&partial, destination).is_ok() {
+        return true;
+    }
+    let _ = fs::remove_file(&partial);
+    false
+}
+
+fn transcode_worker(transcoding: Arc<Mutex<Transcoding>>) {
+    loop {
+        let (file_path, destination) = {
+            let mut transcoding = transcoding.lock().expect("Transcoding lock poisoned");
+            let Some(next) = transcoding.pending.pop_front() else {
+                transcoding.workers -= 1;
+                return;
+            };
+            transcoding.active.insert(next.0.clone());
+            next
+        };
+
+        let converted = transcode(&file_path, &destination);
+
+        let mut transcoding = transcoding.lock().expect("Transcoding lock poisoned");
+        transcoding.active.remove(&file_path);
+        if !converted {
+            println!("Could not transcode {}", file_path);
+            transcoding.failed.insert(destination);
+        }
+    }
+}
+
+pub fn start_transcoding(app_state: &AppState) {
+    let pending: Vec<(String, PathBuf)> = {
+        let transcoding = app_state.transcoding.lock().expect("Transcoding lock poisoned");
+        app_state
+            .loaded_files
+            .values()
+            .flatten()
+            .filter(|file_path| app_state.sound_ids.contains_key(*file_path)) // waits for the hash, which names the cached file
+            .filter(|file_path| !transcoding.is_converting(file_path))
+            .filter(|file_path| detect_format(Path::new(file_path)).is_some_and(|format| !format.is_native()))
+            .map(|file_path| (file_path.clone(), transcoded_path(&sound_id(app_state, file_path))))
+            .filter(|(_, destination)| !destination.exists() && !transcoding.failed.contains(destination))
+            .collect()
+    };
+
+    if pending.is_empty() || !check_ffmpeg() {
+        return;
+    }
+
+    let _ = fs::create_dir_all(TRANSCODE_CACHE_DIRECTORY);
+
+    // a few ffmpegs at a time, a big tab would otherwise start one per file
+    let mut transcoding = app_state.transcoding.lock().expect("Transcoding lock poisoned");
+    transcoding.pending.extend(pending);
+    while transcoding.workers < TRANSCODE_WORKERS.min(transcoding.pending.len()) {
+        transcoding.workers += 1;
+        let transcoding = Arc::clone(&app_state.transcoding);
+        thread::spawn(move || transcode_worker(transcoding));
+    }
+}
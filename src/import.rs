@@ -0,0 +1,409 @@
+use std::{fs::{self, File}, io::{Read, Write}, path::{Path, PathBuf}, sync::{Arc, Mutex}, thread, time::{Duration, Instant}};
+
+use bevy::{prelude::*, window::FileDragAndDrop};
+use bevy_egui::egui::{self, Context};
+
+use crate::{AppState, ALLOWED_FILE_EXTENSIONS, TRANSCODE_FILE_EXTENSIONS, formats::{TRANSCODE_CACHE_DIRECTORY, detect_format, transcode, transcoded_path}, load_data, metadata::hash_file, open_decoder, save_data, virtual_tabs::VIRTUAL_TABS, youtube_downloader::sanitize_filename, yt_dlp::check_ffmpeg};
+
+pub enum UrlImportStatus {
+    Idle,
+    Downloading { downloaded: u64, total: Option<u64> },
+    Done(String),
+    Failed(String),
+}
+
+pub struct UrlImportState {
+    pub url: String,
+    pub filename: String,
+    pub download_directory: String,
+    pub status: Arc<Mutex<UrlImportStatus>>,
+}
+
+pub fn notify(app_state: &mut AppState, message: String) {
+    println!("{}", message);
+    app_state.notification = Some((message, Instant::now()));
+}
+
+pub fn unique_destination(directory: &Path, filename: &str) -> PathBuf { // appends (1), (2)... so imports never overwrite an existing sound
+    let destination = directory.join(filename);
+    if !destination.exists() {
+        return destination;
+    }
+
+    let path = Path::new(filename);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+
+    (1..)
+        .map(|number| directory.join(format!("{} ({}){}", stem, number, extension)))
+        .find(|destination| !destination.exists())
+        .expect("Ran out of numbers for file names")
+}
+
+pub fn add_tab(app_state: &mut AppState, directory: &Path) {
+    let Some(path_str) = directory.to_str() else {
+        notify(app_state, "Invalid path encoding!".to_string());
+        return;
+    };
+
+    if app_state.json_data.tabs.iter().any(|tab| tab == path_str) {
+        notify(app_state, format!("{} is already a tab.", path_str));
+        return;
+    }
+
+    app_state.json_data.tabs.push(path_str.to_string());
+    save_data(app_state);
+    load_data(app_state);
+    app_state.current_directory = path_str.to_string();
+    notify(app_state, format!("Added {} as a new tab.", path_str));
+}
+
+pub fn import_file(app_state: &AppState, file_path: &Path) -> Result<PathBuf, String> {
+    let Some(format) = detect_format(file_path) else {
+        return Err(format!(
+            "{} is not a supported audio file. Supported formats: {}, {} (with FFmpeg)",
+            file_path.display(),
+            ALLOWED_FILE_EXTENSIONS.join(", "),
+            TRANSCODE_FILE_EXTENSIONS.join(", ")
+        ));
+    };
+
+    if !format.is_native() && !check_ffmpeg() {
+        return Err(format!("{} has to be converted before it can be played, which needs FFmpeg installed.", file_path.display()));
+    }
+
+    let tab = app_state.current_directory.clone();
+    if tab.is_empty() || VIRTUAL_TABS.contains(&tab.as_str()) {
+        return Err("Select a folder tab before dropping sounds into the window.".to_string());
+    }
+
+    let filename = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let destination = unique_destination(Path::new(&tab), &filename);
+
+    fs::copy(file_path, &destination).map_err(|err| format!("Could not copy {}: {}", file_path.display(), err))?;
+
+    Ok(destination)
+}
+
+pub fn file_drop_system(mut drop_events: MessageReader<FileDragAndDrop>, mut app_state: ResMut<AppState>) {
+    let mut imported = 0;
+
+    for event in drop_events.read() {
+        let FileDragAndDrop::DroppedFile { path_buf, .. } = event else {
+            continue;
+        };
+
+        if path_buf.is_dir() {
+            add_tab(&mut app_state, path_buf);
+            continue;
+        }
+
+        match import_file(&app_state, path_buf) {
+            Ok(_) => imported += 1,
+            Err(err) => notify(&mut app_state, err),
+        }
+    }
+
+    if imported > 0 {
+        load_data(&mut app_state);
+        let message = format!("Imported {} sound(s) into {}", imported, app_state.current_directory);
+        notify(&mut app_state, message);
+    }
+}
+
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let hex = bytes.get(index + 1..index + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        if bytes[index] == b'%' && let Some(byte) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            decoded.push(byte);
+            index += 3;
+        }
+        else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+// keeps only the last path component, so an encoded separator or .. can't write outside the tab folder
+pub fn safe_filename(name: &str) -> Option<String> {
+    let filename = sanitize_filename(&Path::new(name).file_name()?.to_string_lossy());
+    (!filename.is_empty() && filename != "..").then_some(filename)
+}
+
+pub fn filename_from_url(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let filename = percent_decode(path.rsplit('/').next().unwrap_or_default());
+
+    safe_filename(&filename).unwrap_or_else(|| "download.mp3".to_string())
+}
+
+fn validate_sound(file_path: &Path) -> Result<(), String> {
+    let Some(format) = detect_format(file_path) else {
+        return Err("The downloaded file is not a supported audio file.".to_string());
+    };
+
+    // other formats are converted right away, into the cache entry the library would otherwise create later
+    let mut playable = file_path.to_path_buf();
+    if !format.is_native() {
+        if !check_ffmpeg() {
+            return Err("The downloaded file needs FFmpeg to be played.".to_string());
+        }
+
+        let hash = hash_file(&file_path.to_string_lossy()).ok_or("Could not read the downloaded file.")?;
+        playable = transcoded_path(&hash);
+        let _ = fs::create_dir_all(TRANSCODE_CACHE_DIRECTORY);
+        if !playable.exists() && !transcode(&file_path.to_string_lossy(), &playable) {
+            return Err("The downloaded file could not be converted, FFmpeg does not understand it.".to_string());
+        }
+    }
+
+    let mut decoder = open_decoder(&playable.to_string_lossy())?;
+    if decoder.next().is_none() {
+        return Err("The downloaded file does not contain any audio.".to_string());
+    }
+
+    Ok(())
+}
+
+const DOWNLOAD_CONNECT_TIMEOUT: Duration = Duration::from_secs(15); // only for connecting, big files may take a while to arrive
+
+fn download_url(url: &str, destination: &Path, status: &Arc<Mutex<UrlImportStatus>>) -> Result<(), String> {
+    let client = reqwest::blocking::Client::builder()
+        .connect_timeout(DOWNLOAD_CONNECT_TIMEOUT)
+        .timeout(None)
+        .build()
+        .map_err(|err| format!("Download failed: {}", err))?;
+    let mut response = client
+        .get(url)
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| format!("Download failed: {}", err))?;
+    let total = response.content_length();
+
+    // downloaded into the cache so a library reload never lists the half written file, keeps the extension so the format can still be detected
+    fs::create_dir_all("cache").map_err(|err| format!("Could not create the cache folder: {}", err))?;
+    let extension = destination.extension().unwrap_or_default().to_string_lossy();
+    let partial = Path::new("cache").join(format!("url-import.part.{}", extension));
+
+    let result = write_download(&mut response, &partial, total, status).and_then(|()| validate_sound(&partial)).and_then(|()| {
+        if fs::rename(&partial, destination).is_err() {
+            fs::copy(&partial, destination).map_err(|err| format!("Could not move the download to {}: {}", destination.display(), err))?;
+        }
+        Ok(())
+    });
+
+    let _ = fs::remove_file(&partial);
+    result
+}
+
+fn write_download(response: &mut reqwest::blocking::Response, partial: &Path, total: Option<u64>, status: &Arc<Mutex<UrlImportStatus>>) -> Result<(), String> {
+    let mut file = File::create(partial).map_err(|err| format!("Could not create {}: {}", partial.display(), err))?;
+
+    let mut downloaded = 0;
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = response.read(&mut buffer).map_err(|err| format!("Download failed: {}", err))?;
+        if read == 0 {
+            return Ok(());
+        }
+
+        file.write_all(&buffer[..read]).map_err(|err| format!("Could not write {}: {}", partial.display(), err))?;
+        downloaded += read as u64;
+        *status.lock().expect("URL import lock poisoned") = UrlImportStatus::Downloading { downloaded, total };
+    }
+}
+
+pub fn start_url_import(app_state: &mut AppState, url: String, directory: String, filename: String) {
+    if directory.is_empty() || VIRTUAL_TABS.contains(&directory.as_str()) {
+        notify(app_state, "Select a folder tab to import into.".to_string());
+        return;
+    }
+
+    let status = Arc::clone(&app_state.url_import_state.status);
+    if matches!(*status.lock().expect("URL import lock poisoned"), UrlImportStatus::Downloading { .. }) {
+        notify(app_state, "Another download is still running.".to_string());
+        return;
+    }
+
+    let filename = if filename.trim().is_empty() {
+        filename_from_url(&url)
+    }
+    else {
+        match safe_filename(filename.trim()) {
+            Some(filename) => filename,
+            None => {
+                notify(app_state, format!("{} is not a valid file name.", filename.trim()));
+                return;
+            }
+        }
+    };
+    let destination = unique_destination(Path::new(&directory), &filename);
+    *status.lock().expect("URL import lock poisoned") = UrlImportStatus::Downloading { downloaded: 0, total: None };
+
+    thread::spawn(move || {
+        let result = match download_url(&url, &destination, &status) {
+            Ok(()) => UrlImportStatus::Done(destination.to_string_lossy().to_string()),
+            Err(err) => UrlImportStatus::Failed(err),
+        };
+        *status.lock().expect("URL import lock poisoned") = result;
+    });
+}
+
+pub fn poll_url_import(app_state: &mut AppState) {
+    let status = std::mem::replace(&mut *app_state.url_import_state.status.lock().expect("URL import lock poisoned"), UrlImportStatus::Idle);
+
+    match status {
+        UrlImportStatus::Done(file_path) => {
+            load_data(app_state);
+            notify(app_state, format!("Imported {}", file_path));
+        }
+        UrlImportStatus::Failed(err) => notify(app_state, err),
+        other => *app_state.url_import_state.status.lock().expect("URL import lock poisoned") = other,
+    }
+}
+
+pub fn import_pasted_text(app_state: &mut AppState, text: &str) { // handles both copied files (as paths or file:// uris) and links
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if line.starts_with("http://") || line.starts_with("https://") {
+            let directory = app_state.current_directory.clone();
+            start_url_import(app_state, line.to_string(), directory, String::new());
+            continue;
+        }
+
+        let path = PathBuf::from(percent_decode(line.strip_prefix("file://").unwrap_or(line)));
+        if !path.exists() {
+            notify(app_state, format!("Pasted text is not an audio file path or URL: {}", line));
+            continue;
+        }
+
+        match import_file(app_state, &path) {
+            Ok(destination) => {
+                load_data(app_state);
+                notify(app_state, format!("Imported {}", destination.display()));
+            }
+            Err(err) => notify(app_state, err),
+        }
+    }
+}
+
+pub fn url_import_ui(ctx: &Context, mut app_state: ResMut<AppState>) {
+    egui::CentralPanel::default().show(ctx, |ui| {
+        let available_width = ui.available_width();
+        let available_height = ui.available_height();
+
+        ui.with_layout(egui::Layout::top_down(egui::Align::Center), |ui| {
+            ui.heading("Directory");
+            egui::ComboBox::from_id_salt("URL Import Directory Selector")
+                .selected_text(app_state.url_import_state.download_directory.clone())
+                .width(available_width)
+                .height(available_height / 15.0)
+                .show_ui(ui, |ui| {
+                    for directory in &app_state.loaded_files.keys().cloned().collect::<Vec<_>>() {
+                        ui.selectable_value(
+                            &mut app_state.url_import_state.download_directory,
+                            directory.clone(),
+                            directory,
+                        );
+                    }
+                });
+
+            ui.heading("Audio URL");
+            let url_response = ui.add_sized([available_width, available_height / 20.0], egui::TextEdit::singleline(&mut app_state.url_import_state.url));
+            if url_response.changed() {
+                app_state.url_import_state.filename = filename_from_url(&app_state.url_import_state.url);
+            }
+
+            ui.heading("Filename");
+            ui.add_sized([available_width, available_height / 20.0], egui::TextEdit::singleline(&mut app_state.url_import_state.filename));
+        });
+
+        if let UrlImportStatus::Downloading { downloaded, total } = *app_state.url_import_state.status.lock().expect("URL import lock poisoned") {
+            let progress_bar = match total {
+                Some(total) if total > 0 => egui::ProgressBar::new(downloaded as f32 / total as f32).text(format!("{} / {} KB", downloaded / 1024, total / 1024)),
+                _ => egui::ProgressBar::new(0.0).animate(true).text(format!("{} KB", downloaded / 1024)),
+            };
+            ui.add(progress_bar);
+        }
+
+        if ui
+            .add_sized(
+                [available_width, available_height / 15.0],
+                egui::Button::new("Import Sound"),
+            )
+            .clicked()
+        {
+            let url = app_state.url_import_state.url.trim().to_string();
+            let directory = app_state.url_import_state.download_directory.clone();
+            let filename = app_state.url_import_state.filename.clone();
+            start_url_import(&mut app_state, url, directory, filename);
+        };
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    #[test]
+    fn url_filenames_stay_inside_the_tab_folder() {
+        assert_eq!(filename_from_url("https://host/sounds/airhorn%20long.mp3?x=1"), "airhorn long.mp3");
+        assert_eq!(filename_from_url("https://host/a%2F..%2F..%2Fx.mp3"), "x.mp3");
+        assert_eq!(filename_from_url("https://host/%2Fetc%2Fpasswd"), "passwd");
+        assert!(!filename_from_url("https://host/..%5C..%5Cx.mp3").contains(['/', '\\']));
+        assert_eq!(filename_from_url("https://host/%2E%2E"), "download.mp3");
+        assert_eq!(filename_from_url("https://host/"), "download.mp3");
+    }
+
+    #[test]
+    fn rejects_downloads_that_do_not_decode() {
+        let directory = temp_dir("import-validate");
+
+        let broken_wav = directory.join("broken.wav");
+        fs::write(&broken_wav, b"RIFF\0\0\0\0WAVEjunk").unwrap();
+        assert!(validate_sound(&broken_wav).is_err());
+
+        let broken_opus = directory.join("broken.opus");
+        fs::write(&broken_opus, b"OggS\0\x02 not really opus").unwrap();
+        assert!(validate_sound(&broken_opus).is_err()); // either ffmpeg is missing or it can't convert this
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn failed_downloads_leave_nothing_behind() {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let _ = request.respond(tiny_http::Response::from_string("<html>not a sound</html>"));
+            }
+        });
+
+        let tab = temp_dir("url-import");
+        let status = Arc::new(Mutex::new(UrlImportStatus::Idle));
+
+        assert!(download_url(&format!("http://{}/airhorn.mp3", address), &tab.join("airhorn.mp3"), &status).is_err());
+        assert_eq!(fs::read_dir(&tab).unwrap().count(), 0);
+        assert!(!Path::new("cache/url-import.part.mp3").exists());
+
+        fs::remove_dir_all(&tab).unwrap();
+    }
+
+    #[test]
+    fn rejects_typed_filenames_that_are_not_a_name() {
+        assert_eq!(safe_filename("clip.mp3"), Some("clip.mp3".to_string()));
+        assert_eq!(safe_filename("../clip.mp3"), Some("clip.mp3".to_string()));
+        assert_eq!(safe_filename("/tmp/clip.mp3"), Some("clip.mp3".to_string()));
+        assert_eq!(safe_filename(".."), None);
+        assert_eq!(safe_filename("/"), None);
+        assert_eq!(safe_filename(""), None);
+    }
+}
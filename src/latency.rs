@@ -0,0 +1,208 @@
+use std::{sync::Arc, thread, time::{Duration, Instant}};
+
+use bevy_egui::egui::{self, Color32, Ui};
+use rodio::{Sink, buffer::SamplesBuffer, mixer::Mixer};
+use serde::{Deserialize, Serialize};
+
+use crate::{AppState, save_data};
+
+const CLICK_SAMPLE_RATE: u32 = 48_000;
+const WARMUP: Duration = Duration::from_millis(300);
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct LatencySettings {
+    pub loopback_latency_msec: u32, // latency_msec of the pactl loopbacks
+    pub ring_buffer_msec: u32, // size of the mic to VB Cable buffer on windows
+}
+
+impl Default for LatencySettings {
+    fn default() -> Self {
+        LatencySettings {
+            loopback_latency_msec: 1,
+            ring_buffer_msec: 1000,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum LatencyMeasurement {
+    Idle,
+    Measuring,
+    Done(f32), // milliseconds
+    Failed(String),
+}
+
+fn click_source() -> SamplesBuffer {
+    // 5 ms square burst, loud and sharp enough to stand out from the mic noise
+    let samples: Vec<f32> = (0..CLICK_SAMPLE_RATE / 200).map(|index| if index % 48 < 24 { 0.9 } else { -0.9 }).collect();
+    SamplesBuffer::new(1, CLICK_SAMPLE_RATE, samples)
+}
+
+pub fn find_click(samples: &[f32], threshold: f32) -> Option<usize> {
+    samples.iter().position(|sample| sample.abs() >= threshold)
+}
+
+// plays a click into the soundboard and waits for it on the virtual mic monitor, returns the round trip in milliseconds
+fn measure(mixer: &Mixer) -> Result<f32, String> {
+    let started = Instant::now();
+    let mut noise_peak: f32 = 0.0;
+    let mut played: Option<(Instant, Sink)> = None;
+    let mut result = Err("The click never arrived at the virtual mic, is the soundboard routed and unmuted?".to_string());
+
+    capture_monitor(&mut |chunk, arrived, sample_rate| {
+        if started.elapsed() > TIMEOUT {
+            return true;
+        }
+
+        match &played {
+            None => {
+                noise_peak = chunk.iter().fold(noise_peak, |peak, sample| peak.max(sample.abs()));
+                if started.elapsed() >= WARMUP {
+                    let sink = Sink::connect_new(mixer);
+                    sink.append(click_source());
+                    played = Some((Instant::now(), sink));
+                }
+                false
+            }
+            Some((played_at, _)) => {
+                let threshold = (noise_peak * 2.0).max(0.25);
+                if noise_peak >= 0.45 {
+                    result = Err("The virtual mic is too noisy to measure, mute your microphone and try again.".to_string());
+                    return true;
+                }
+
+                let Some(index) = find_click(chunk, threshold) else {
+                    return false;
+                };
+                // the chunk arrived after its last sample was recorded, step back to the click itself
+                let after_click = Duration::from_secs_f32((chunk.len() - index) as f32 / sample_rate as f32);
+                result = Ok(arrived.saturating_duration_since(*played_at).saturating_sub(after_click).as_secs_f32() * 1000.0);
+                true
+            }
+        }
+    })?;
+
+    result
+}
+
+#[cfg(target_os = "linux")]
+fn capture_monitor(on_chunk: &mut dyn FnMut(&[f32], Instant, u32) -> bool) -> Result<(), String> {
+    use std::{io::Read, process::{Command, Stdio}};
+
+    // pipewire link mode has no virtual mic, the soundboard sink is the closest thing apps hear
+    let monitor = if crate::linux_lib::get_virtual_mic_source_index().is_some() { "VirtualMic.monitor" } else { "SoundboardSink.monitor" };
+    let mut child = Command::new("parec")
+        .args(["-d", monitor, "--raw", "--format=float32le", "--rate=48000", "--channels=1", "--latency-msec=5"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("Could not start parec: {}", err))?;
+    let mut stdout = child.stdout.take().ok_or("parec has no output")?;
+
+    let mut buffer = [0u8; 480 * 4]; // 10 ms
+    loop {
+        if stdout.read_exact(&mut buffer).is_err() {
+            break;
+        }
+        let chunk: Vec<f32> = buffer.chunks_exact(4).map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).collect();
+        if on_chunk(&chunk, Instant::now(), 48_000) {
+            break;
+        }
+    }
+
+    let _ = child.kill();
+    let _ = child.wait();
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn capture_monitor(on_chunk: &mut dyn FnMut(&[f32], Instant, u32) -> bool) -> Result<(), String> {
+    crate::windows_lib::capture_cable_output(on_chunk)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn capture_monitor(_on_chunk: &mut dyn FnMut(&[f32], Instant, u32) -> bool) -> Result<(), String> {
+    Err("Latency measurement is not supported on this platform.".to_string())
+}
+
+pub fn start_latency_measurement(app_state: &AppState) {
+    let measurement = Arc::clone(&app_state.latency_measurement);
+    *measurement.lock().expect("Latency measurement lock poisoned") = LatencyMeasurement::Measuring;
+
+    let mixer = app_state.sound_system.mixer.clone();
+    thread::spawn(move || {
+        let result = match measure(&mixer) {
+            Ok(milliseconds) => LatencyMeasurement::Done(milliseconds),
+            Err(err) => LatencyMeasurement::Failed(err),
+        };
+        *measurement.lock().expect("Latency measurement lock poisoned") = result;
+    });
+}
+
+pub fn latency_settings_ui(ui: &mut Ui, app_state: &mut AppState) {
+    ui.heading("Latency");
+
+    let mut settings = app_state.json_data.latency;
+    ui.horizontal(|ui| {
+        if cfg!(target_os = "windows") {
+            ui.label("Mic buffer (ms)");
+            ui.add(egui::DragValue::new(&mut settings.ring_buffer_msec).range(20..=2000));
+        }
+        else {
+            ui.label("Loopback latency (ms)");
+            ui.add(egui::DragValue::new(&mut settings.loopback_latency_msec).range(1..=500));
+        }
+    });
+    ui.label("Raise this if sounds or your voice crackle. Changes apply after Reload sound system.");
+
+    if settings != app_state.json_data.latency {
+        app_state.json_data.latency = settings;
+        save_data(app_state);
+    }
+
+    let measurement = app_state.latency_measurement.lock().expect("Latency measurement lock poisoned").clone();
+    ui.horizontal(|ui| {
+        if ui.add_enabled(measurement != LatencyMeasurement::Measuring, egui::Button::new("Measure latency")).clicked() {
+            start_latency_measurement(app_state);
+        }
+
+        match measurement {
+            LatencyMeasurement::Idle => {
+                ui.label("Plays a click and times how long it takes to reach the virtual mic. Stay quiet while it runs.");
+            }
+            LatencyMeasurement::Measuring => {
+                ui.spinner();
+            }
+            LatencyMeasurement::Done(milliseconds) => {
+                ui.label(format!("Round trip: {:.0} ms", milliseconds));
+            }
+            LatencyMeasurement::Failed(err) => {
+                ui.colored_label(Color32::RED, err);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::Source;
+
+    #[test]
+    fn finds_the_click_in_a_synthetic_recording() {
+        let mut recording = vec![0.01; 480];
+        recording[300] = -0.05;
+        recording.extend(click_source().take(10));
+
+        assert_eq!(find_click(&recording, 0.25), Some(480));
+        assert_eq!(find_click(&recording[..480], 0.25), None);
+    }
+
+    #[test]
+    fn click_is_short_and_loud() {
+        let click = click_source();
+        assert_eq!(click.total_duration(), Some(Duration::from_millis(5)));
+        assert!(click.clone().all(|sample| sample.abs() == 0.9));
+    }
+}
@@ -0,0 +1,104 @@
+use bevy_egui::egui::{self, Ui};
+use serde::{Deserialize, Serialize};
+
+use crate::{AppState, save_data, metadata::{sound_button, sound_display_name}};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LayoutMode {
+    Grid,
+    List,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct TabLayout {
+    pub mode: LayoutMode,
+    pub columns: usize,
+    pub button_height: f32,
+    pub wrap_text: bool,
+}
+
+impl Default for TabLayout {
+    fn default() -> Self {
+        TabLayout {
+            mode: LayoutMode::Grid,
+            columns: 4,
+            button_height: 80.0,
+            wrap_text: true,
+        }
+    }
+}
+
+pub fn get_tab_layout(app_state: &AppState, tab: &str) -> TabLayout {
+    let mut layout = app_state.json_data.tab_layouts.get(tab).copied().unwrap_or_default();
+    layout.columns = layout.columns.clamp(1, 12); // data.json may be edited by hand, the grid needs at least one column
+    layout
+}
+
+pub fn layout_settings_ui(ui: &mut Ui, app_state: &mut AppState) {
+    let tab = app_state.current_directory.clone();
+    let mut layout = get_tab_layout(app_state, &tab);
+
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_salt("Layout Mode Selector")
+            .selected_text(match layout.mode {
+                LayoutMode::Grid => "Grid",
+                LayoutMode::List => "Compact list",
+            })
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut layout.mode, LayoutMode::Grid, "Grid");
+                ui.selectable_value(&mut layout.mode, LayoutMode::List, "Compact list");
+            });
+
+        if layout.mode == LayoutMode::Grid {
+            ui.label("Columns");
+            ui.add(egui::DragValue::new(&mut layout.columns).range(1..=12));
+            ui.label("Button size");
+            ui.add(egui::Slider::new(&mut layout.button_height, 30.0..=200.0));
+            ui.checkbox(&mut layout.wrap_text, "Wrap text");
+        }
+    });
+
+    if layout != get_tab_layout(app_state, &tab) {
+        app_state.json_data.tab_layouts.insert(tab, layout);
+        save_data(app_state);
+    }
+}
+
+pub fn sound_grid_ui(ui: &mut Ui, app_state: &mut AppState, files: &[String]) -> Option<String> { // returns the clicked sound
+    let layout = get_tab_layout(app_state, &app_state.current_directory);
+    let mut clicked = None;
+
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        match layout.mode {
+            LayoutMode::Grid => {
+                let spacing = ui.spacing().item_spacing.x;
+                let button_width = (ui.available_width() - spacing * (layout.columns - 1) as f32) / layout.columns as f32;
+
+                for row in files.chunks(layout.columns) {
+                    ui.horizontal(|ui| {
+                        for file_path in row {
+                            let display_name = sound_display_name(app_state, file_path);
+                            if sound_button(ui, app_state, file_path, display_name, [button_width, layout.button_height], false, layout.wrap_text).clicked() {
+                                clicked = Some(file_path.clone());
+                            }
+                        }
+                    });
+                }
+            }
+            LayoutMode::List => {
+                let row_height = ui.text_style_height(&egui::TextStyle::Button) + ui.spacing().button_padding.y * 2.0;
+
+                for file_path in files {
+                    let display_name = sound_display_name(app_state, file_path);
+                    if sound_button(ui, app_state, file_path, display_name, [ui.available_width(), row_height], false, false).clicked() {
+                        clicked = Some(file_path.clone());
+                    }
+                }
+            }
+        }
+    });
+
+    clicked
+}
@@ -1,12 +1,19 @@
 use rodio::{
     OutputStream, OutputStreamBuilder,
-    cpal::{self, traits::HostTrait},
+    cpal::{self, traits::{DeviceTrait, HostTrait}},
 };
 use serde_json::Value;
-use std::process::Command;
+use std::{env, ffi::OsString, process::Command, sync::Mutex};
 
-const APPS_TO_EXCLUDE: [&str; 7] = ["plasmashell", "pavucontrol", "pipewire", "wireplumber", "kwin_wayland", "kwin_x11", "obs"];
-const NODE_NAMES_TO_EXCLUDE: [&str; 2] = ["VirtualMicSource", "SoundboardSink"];
+use crate::{audio_format::pick_config, routing::{AppOutput, RoutingMode}};
+
+struct PipeWirePort {
+    id: u64,
+    node_id: u64,
+    is_output: bool,
+    name: String,
+    channel: String,
+}
 
 fn pactl_list(sink_type: &str) -> Value {
     let command_output = Command::new("pactl")
@@ -24,17 +31,13 @@ fn pactl_list(sink_type: &str) -> Value {
     }
 }
 
-pub fn get_soundboard_sink_index() -> String {
-    let source_outputs = pactl_list("sinks");
-    source_outputs
-        .as_array()
-        .unwrap_or(&vec![])
+pub fn get_virtual_mic_source_index() -> Option<String> {
+    let sources = pactl_list("sources");
+    sources
+        .as_array()?
         .iter()
-        .find(|sink| sink["name"] == "SoundboardSink")
-        .and_then(|sink| {
-            Some(sink["index"].to_string())
-        })
-        .unwrap()
+        .find(|source| source["name"] == "VirtualMicSource")
+        .map(|source| source["index"].to_string())
 }
 
 pub fn get_default_source() -> String {
@@ -80,7 +83,7 @@ pub fn move_playback_to_sink() {
     }
 }
 
-pub fn list_outputs() -> Vec<(String, String)> {
+pub fn list_outputs() -> Vec<AppOutput> {
     let source_outputs = pactl_list("source-outputs");
     return source_outputs
         .as_array()
@@ -92,14 +95,14 @@ pub fn list_outputs() -> Vec<(String, String)> {
             let binary = sink["properties"]["application.process.binary"]
                 .as_str()
                 .unwrap_or("Unknown");
-            if APPS_TO_EXCLUDE.contains(&binary) || NODE_NAMES_TO_EXCLUDE.contains(&node_name) {
-                return None;
-            }
             let index = sink["index"]
                 .as_u64()
                 .expect("sink index is not a number")
                 .to_string();
-            Some((format!("{} ({})", app_name, binary), index))
+            let source = sink["source"].to_string();
+            // pipewire-pulse exposes the pipewire node id, which is what links are made against
+            let object_id = sink["properties"]["object.id"].as_str().map(str::to_string).unwrap_or_else(|| sink["properties"]["object.id"].to_string());
+            Some(AppOutput { index, app_name: app_name.to_string(), binary: binary.to_string(), node_name: node_name.to_string(), source, object_id })
         })
         .collect();
 }
@@ -111,7 +114,100 @@ pub fn move_output_to_sink(output_index: String, sink_index: String) {
         .expect("Failed to execute process");
 }
 
-pub fn create_virtual_mic_linux() -> OutputStream {
+pub fn pipewire_available() -> bool {
+    let pw_link_works = Command::new("pw-link").arg("--version").output().is_ok_and(|output| output.status.success());
+    let server_info = Command::new("pactl").arg("info").output().map(|output| String::from_utf8_lossy(&output.stdout).to_string()).unwrap_or_default();
+
+    pw_link_works && server_info.contains("PipeWire")
+}
+
+fn pw_dump() -> Value {
+    Command::new("pw-dump")
+        .output()
+        .ok()
+        .and_then(|output| serde_json::from_slice(&output.stdout).ok())
+        .unwrap_or(Value::Null)
+}
+
+fn pipewire_ports(dump: &Value) -> Vec<PipeWirePort> {
+    dump.as_array()
+        .unwrap_or(&vec![])
+        .iter()
+        .filter(|object| object["type"] == "PipeWire:Interface:Port")
+        .filter_map(|port| {
+            let props = &port["info"]["props"];
+            Some(PipeWirePort {
+                id: port["id"].as_u64()?,
+                node_id: props["node.id"].as_u64()?,
+                is_output: port["info"]["direction"] == "output",
+                name: props["port.name"].as_str().unwrap_or_default().to_string(),
+                channel: props["audio.channel"].as_str().unwrap_or_default().to_string(),
+            })
+        })
+        .collect()
+}
+
+fn pipewire_node_id(dump: &Value, node_name: &str) -> Option<u64> {
+    dump.as_array()?
+        .iter()
+        .find(|object| object["type"] == "PipeWire:Interface:Node" && object["info"]["props"]["node.name"] == node_name)
+        .and_then(|node| node["id"].as_u64())
+}
+
+// pairs of (soundboard monitor port, app input port) with matching channels
+fn soundboard_link_pairs(dump: &Value, app_node_id: u64) -> Vec<(u64, u64)> {
+    let Some(soundboard_node_id) = pipewire_node_id(dump, "SoundboardSink") else {
+        return Vec::new();
+    };
+    let ports = pipewire_ports(dump);
+    let monitors: Vec<&PipeWirePort> = ports
+        .iter()
+        .filter(|port| port.node_id == soundboard_node_id && port.is_output && port.name.starts_with("monitor_"))
+        .collect();
+
+    let mut pairs = Vec::new();
+    for input in ports.iter().filter(|port| port.node_id == app_node_id && !port.is_output) {
+        let matching: Vec<&&PipeWirePort> = monitors.iter().filter(|monitor| monitor.channel == input.channel).collect();
+        if matching.is_empty() { // a mono input gets every channel, pipewire mixes them down
+            pairs.extend(monitors.iter().map(|monitor| (monitor.id, input.id)));
+        }
+        else {
+            pairs.extend(matching.iter().map(|monitor| (monitor.id, input.id)));
+        }
+    }
+    pairs
+}
+
+pub fn set_soundboard_link(app_node_id: &str, linked: bool) {
+    let Ok(app_node_id) = app_node_id.parse() else {
+        return;
+    };
+
+    for (output_port, input_port) in soundboard_link_pairs(&pw_dump(), app_node_id) {
+        let mut command = Command::new("pw-link");
+        if !linked {
+            command.arg("-d");
+        }
+        let _ = command.arg(output_port.to_string()).arg(input_port.to_string()).output();
+    }
+}
+
+pub fn create_virtual_mic_linux(routing_mode: RoutingMode, latency_msec: u32) -> (OutputStream, RoutingMode) {
+    let routing_mode = setup_virtual_mic(routing_mode, latency_msec);
+    (open_soundboard_output(), routing_mode)
+}
+
+// loads the pactl modules, returns the routing mode that could actually be set up
+pub fn setup_virtual_mic(routing_mode: RoutingMode, latency_msec: u32) -> RoutingMode {
+    let routing_mode = if routing_mode == RoutingMode::PipewireLinks && !pipewire_available() {
+        println!("PipeWire is not running, falling back to routing through the virtual mic.");
+        RoutingMode::Pactl
+    }
+    else {
+        routing_mode
+    };
+    let latency = format!("latency_msec={}", latency_msec);
+
     Command::new("pactl")
         .args(&[
             "load-module",
@@ -122,6 +218,96 @@ pub fn create_virtual_mic_linux() -> OutputStream {
         .output()
         .expect("Failed to create SoundboardSink");
 
+    // Soundboard audio -> speakers
+    Command::new("pactl")
+        .args(&[
+            "load-module",
+            "module-loopback",
+            "source=SoundboardSink.monitor",
+            "sink=@DEFAULT_SINK@",
+            latency.as_str(),
+        ])
+        .output()
+        .expect("Failed to create soundboard to speakers loopback");
+
+    // with links, apps keep their real mic and get the soundboard linked in next to it, so the virtual mic isn't needed
+    if routing_mode == RoutingMode::Pactl {
+        create_virtual_mic_modules(&latency);
+    }
+
+    Command::new("pactl")
+        .args(&["set-sink-volume", "SoundboardSink", "100%"])
+        .output()
+        .expect("Failed to set soundboard volume");
+
+    routing_mode
+}
+
+pub fn open_soundboard_output() -> OutputStream {
+    open_soundboard_stream().unwrap_or_else(|| {
+        println!("No pulse or pipewire ALSA device found, moving the soundboard stream into SoundboardSink instead.");
+        let host = cpal::host_from_id(cpal::HostId::Alsa).expect("Could not initialize ALSA");
+        let device = host
+            .default_output_device()
+            .expect("Could not get default output device");
+        let stream = open_output_stream(device).expect("Failed to open stream");
+        move_playback_to_sink();
+        stream
+    })
+}
+
+pub fn soundboard_sink_exists() -> bool {
+    pactl_list("sinks")
+        .as_array()
+        .is_some_and(|sinks| sinks.iter().any(|sink| sink["name"] == "SoundboardSink"))
+}
+
+fn open_output_stream(device: cpal::Device) -> Result<OutputStream, rodio::StreamError> {
+    let config = pick_config(
+        device.supported_output_configs().map(|configs| configs.collect::<Vec<_>>()).unwrap_or_default(),
+        device.default_output_config().map_err(rodio::StreamError::DefaultStreamConfigError)?,
+    );
+
+    OutputStreamBuilder::from_device(device)?
+        .with_supported_config(&config)
+        .open_stream()
+}
+
+// the pulse and pipewire ALSA plugins connect a new stream to the sink named in these variables, they are only set while the
+// soundboard stream opens so every other stream of the process, like the downloader preview, stays on the speakers
+fn open_soundboard_stream() -> Option<OutputStream> {
+    static OPENING: Mutex<()> = Mutex::new(());
+    let _opening = OPENING.lock().expect("Soundboard stream lock poisoned");
+
+    let previous: Vec<(&str, Option<OsString>)> = ["PULSE_SINK", "PIPEWIRE_NODE"].into_iter().map(|name| (name, env::var_os(name))).collect();
+    // SAFETY: the audio libraries only read the environment while opening a stream, which happens here or for the preview,
+    // and both run in systems that hold the AppState, so never at the same time
+    unsafe {
+        env::set_var("PULSE_SINK", "SoundboardSink");
+        env::set_var("PIPEWIRE_NODE", "SoundboardSink");
+    }
+
+    let host = cpal::host_from_id(cpal::HostId::Alsa).expect("Could not initialize ALSA");
+    let stream = host
+        .output_devices()
+        .ok()
+        .and_then(|mut devices| devices.find(|device| device.name().is_ok_and(|name| name == "pulse" || name == "pipewire")))
+        .and_then(|device| open_output_stream(device).ok());
+
+    for (name, value) in previous {
+        // SAFETY: same as above
+        unsafe {
+            match value {
+                Some(value) => env::set_var(name, value),
+                None => env::remove_var(name),
+            }
+        }
+    }
+
+    stream
+}
+
+fn create_virtual_mic_modules(latency: &str) {
     Command::new("pactl")
         .args(&[
             "load-module",
@@ -143,18 +329,6 @@ pub fn create_virtual_mic_linux() -> OutputStream {
         .output()
         .expect("Failed to create VirtualMicSource");
 
-    // Soundboard audio -> speakers
-    Command::new("pactl")
-        .args(&[
-            "load-module",
-            "module-loopback",
-            "source=SoundboardSink.monitor",
-            "sink=@DEFAULT_SINK@",
-            "latency_msec=1",
-        ])
-        .output()
-        .expect("Failed to create soundboard to speakers loopback");
-
     // Soundboard audio -> VirtualMic
     Command::new("pactl")
         .args(&[
@@ -162,7 +336,7 @@ pub fn create_virtual_mic_linux() -> OutputStream {
             "module-loopback",
             "source=SoundboardSink.monitor",
             "sink=VirtualMic",
-            "latency_msec=1",
+            latency,
         ])
         .output()
         .expect("Failed to create soundboard to VirtualMic loopback");
@@ -174,7 +348,7 @@ pub fn create_virtual_mic_linux() -> OutputStream {
             "module-loopback",
             "source=@DEFAULT_SOURCE@",
             "sink=VirtualMic",
-            "latency_msec=1",
+            latency,
         ])
         .output()
         .expect("Failed to create microphone loopback");
@@ -183,25 +357,6 @@ pub fn create_virtual_mic_linux() -> OutputStream {
         .args(&["set-sink-volume", "VirtualMic", "100%"])
         .output()
         .expect("Failed to set volume");
-
-    Command::new("pactl")
-        .args(&["set-sink-volume", "SoundboardSink", "100%"])
-        .output()
-        .expect("Failed to set soundboard volume");
-
-    let host = cpal::host_from_id(cpal::HostId::Alsa).expect("Could not initialize ALSA");
-    let device = host
-        .default_output_device()
-        .expect("Could not get default output device");
-
-    let stream = OutputStreamBuilder::from_device(device)
-        .expect("Unable to open VirtualMic")
-        .open_stream()
-        .expect("Failed to open stream");
-
-    move_playback_to_sink();
-
-    return stream;
 }
 
 pub fn reload_sound() {
@@ -224,3 +379,42 @@ pub fn reload_sound() {
         println!("Error: {}", String::from_utf8_lossy(&output.stderr));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn port(id: u64, node_id: u64, direction: &str, name: &str, channel: &str) -> Value {
+        serde_json::json!({
+            "id": id,
+            "type": "PipeWire:Interface:Port",
+            "info": { "direction": direction, "props": { "node.id": node_id, "port.name": name, "audio.channel": channel } }
+        })
+    }
+
+    fn dump(app_ports: Vec<Value>) -> Value {
+        let mut objects = vec![
+            serde_json::json!({ "id": 10, "type": "PipeWire:Interface:Node", "info": { "props": { "node.name": "SoundboardSink" } } }),
+            port(11, 10, "input", "playback_FL", "FL"),
+            port(12, 10, "input", "playback_FR", "FR"),
+            port(13, 10, "output", "monitor_FL", "FL"),
+            port(14, 10, "output", "monitor_FR", "FR"),
+        ];
+        objects.extend(app_ports);
+        Value::Array(objects)
+    }
+
+    #[test]
+    fn links_matching_channels() {
+        let dump = dump(vec![port(21, 20, "input", "input_FL", "FL"), port(22, 20, "input", "input_FR", "FR"), port(23, 20, "output", "capture_FL", "FL")]);
+        assert_eq!(soundboard_link_pairs(&dump, 20), vec![(13, 21), (14, 22)]);
+    }
+
+    #[test]
+    fn links_every_channel_into_mono_inputs() {
+        let dump = dump(vec![port(31, 30, "input", "input_MONO", "MONO")]);
+        assert_eq!(soundboard_link_pairs(&dump, 30), vec![(13, 31), (14, 31)]);
+        assert_eq!(soundboard_link_pairs(&dump, 99), vec![]);
+        assert_eq!(soundboard_link_pairs(&Value::Array(vec![]), 30), vec![]);
+    }
+}
@@ -1,11 +1,26 @@
 use bevy::{log::Level, prelude::*};
-use bevy_egui::{EguiContextSettings, EguiContexts, EguiPrimaryContextPass, EguiStartupSet, egui::{self, Context, TextBuffer, Ui, ecolor::Color32}};
+use bevy_egui::{EguiContextSettings, EguiContexts, EguiPrimaryContextPass, EguiStartupSet, egui::{self, Context, Ui, ecolor::Color32}};
 
-use std::{collections::HashMap, fs::{File, create_dir, exists, rename}, io::{BufReader, Read, Seek}, path::Path, process::{Command, Stdio}, sync::{Arc, Mutex}, thread, time::Instant};
+use std::{collections::{HashMap, HashSet}, fs::{File, create_dir, exists}, io::{BufReader, Read, Seek}, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::Instant};
 
 use serde::{Deserialize, Serialize};
 
 mod yt_dlp;
+mod search;
+mod metadata;
+mod virtual_tabs;
+mod stats;
+mod layout;
+mod import;
+mod formats;
+mod youtube_downloader;
+mod setup;
+mod routing;
+mod latency;
+mod audio_format;
+mod cli;
+mod control;
+mod remote;
 
 #[cfg(target_os = "linux")]
 mod linux_lib;
@@ -13,16 +28,58 @@ mod linux_lib;
 #[cfg(target_os = "windows")]
 mod windows_lib;
 
+#[cfg(test)]
+mod test_support;
+
 use rodio::{
     Decoder, OutputStream, OutputStreamBuilder, Sink, Source,
     cpal::{self, traits::HostTrait},
+    mixer::Mixer,
 };
 
 use crate::yt_dlp::*;
-
-#[derive(Serialize, Deserialize)]
+use crate::search::*;
+use crate::metadata::*;
+use crate::virtual_tabs::*;
+use crate::stats::*;
+use crate::layout::*;
+use crate::import::*;
+use crate::formats::*;
+use crate::youtube_downloader::*;
+use crate::setup::*;
+use crate::routing::*;
+use crate::latency::*;
+use crate::cli::*;
+use crate::control::*;
+use crate::remote::*;
+
+#[derive(Serialize, Deserialize, Default)]
 struct JSONData {
     tabs: Vec<String>,
+    #[serde(default)]
+    sound_metadata: HashMap<String, SoundMetadata>,
+    #[serde(default)]
+    favorites: Vec<String>,
+    #[serde(default)]
+    recent: Vec<String>,
+    #[serde(default)]
+    sort_by_play_count: bool,
+    #[serde(default)]
+    tab_layouts: HashMap<String, TabLayout>,
+    #[serde(default)]
+    yt_dlp: YtDlpSettings,
+    #[serde(default)]
+    setup: SetupSettings,
+    #[serde(default)]
+    routing_rules: Vec<RoutingRule>,
+    #[serde(default)]
+    exclusions: ExclusionList,
+    #[serde(default)]
+    routing_mode: RoutingMode,
+    #[serde(default)]
+    latency: LatencySettings,
+    #[serde(default)]
+    remote: RemoteSettings,
 }
 
 #[allow(dead_code)]
@@ -31,22 +88,59 @@ struct PlayingSound {
     length: f32,
     sink: Sink,
     to_remove: bool,
+    started_at: u64,
+    trigger: PlayTrigger,
     #[cfg(target_os = "windows")]
     normal_sink: Sink,
 }
 
 struct SoundSystem {
     #[cfg(target_os = "windows")]
-    normal_output_stream: OutputStream,
-    output_stream: OutputStream,
+    #[allow(dead_code)] // only held so the device stays open
+    normal_output_stream: Option<OutputStream>,
+    #[cfg(target_os = "windows")]
+    normal_mixer: Mixer,
+    #[cfg(target_os = "windows")]
+    mic_route: std::sync::mpsc::Sender<()>, // dropping it stops copying the mic into VB Cable
+    #[allow(dead_code)] // only held so the device stays open
+    output_stream: Option<OutputStream>,
+    mixer: Mixer, // what the sounds play into
+    #[cfg(target_os = "linux")]
+    routing_mode: RoutingMode, // the mode actually in use, pipewire links fall back to pactl
 }
 
-struct YoutubeDownloaderState {
-    current_url: String,
-    current_filename: String,
-    download_directory: String,
-    yt_dlp_running: bool,
-    yt_dlp_stdout_text: Arc<Mutex<String>>
+impl SoundSystem {
+    fn new(output_stream: OutputStream) -> Self {
+        SoundSystem {
+            #[cfg(target_os = "windows")]
+            normal_output_stream: None,
+            #[cfg(target_os = "windows")]
+            normal_mixer: rodio::mixer::mixer(2, 48_000).0,
+            #[cfg(target_os = "windows")]
+            mic_route: std::sync::mpsc::channel().0,
+            mixer: output_stream.mixer().clone(),
+            output_stream: Some(output_stream),
+            #[cfg(target_os = "linux")]
+            routing_mode: RoutingMode::default(),
+        }
+    }
+
+    // sounds go into mixers nothing plays, so tests can drive an AppState without an audio device
+    #[cfg(test)]
+    fn null() -> Self {
+        SoundSystem {
+            #[cfg(target_os = "windows")]
+            normal_output_stream: None,
+            #[cfg(target_os = "windows")]
+            normal_mixer: rodio::mixer::mixer(2, 48_000).0,
+            #[cfg(target_os = "windows")]
+            mic_route: std::sync::mpsc::channel().0,
+            output_stream: None,
+            mixer: rodio::mixer::mixer(2, 48_000).0,
+            #[cfg(target_os = "linux")]
+            routing_mode: RoutingMode::default(),
+        }
+    }
 }
 
 #[derive(Resource)]
@@ -56,29 +150,51 @@ struct AppState {
     current_directory: String,
     currently_playing: Vec<PlayingSound>,
     sound_system: SoundSystem,
-    virt_outputs: Vec<(String, String)>,
-    is_virt_output_used: HashMap<String, bool>,
+    virt_outputs: Vec<AppOutput>,
+    applied_routing: HashMap<String, AppliedRouting>,
     last_virt_output_update: Instant,
     current_view: String,
-    youtube_downloader_state: YoutubeDownloaderState
+    youtube_downloader_state: YoutubeDownloaderState,
+    search_state: SearchState,
+    sound_ids: HashMap<String, String>,
+    edited_sound: Option<EditSoundState>,
+    play_history: Vec<PlayRecord>,
+    notification: Option<(String, Instant)>,
+    transcoding: Arc<Mutex<Transcoding>>,
+    hashing: Arc<Mutex<HashSet<String>>>,
+    hashed_sounds: Arc<Mutex<Vec<(String, CachedHash)>>>,
+    url_import_state: UrlImportState,
+    yt_dlp_status: Arc<Mutex<YtDlpStatus>>,
+    ffmpeg_status: Arc<Mutex<FfmpegStatus>>,
+    routing_ui_state: RoutingUiState,
+    latency_measurement: Arc<Mutex<LatencyMeasurement>>,
+    volume: f32, // set over the control socket, applies to every sound
+    control_requests: Arc<Mutex<Vec<ControlRequest>>>,
+    remote_server: Option<RemoteServer>
 }
 
-const ALLOWED_FILE_EXTENSIONS: [&str; 4] = ["mp3", "wav", "flac", "ogg"];
+const ALLOWED_FILE_EXTENSIONS: [&str; 10] = ["mp3", "wav", "flac", "ogg", "oga", "m4a", "mp4", "aac", "aif", "aiff"];
+const TRANSCODE_FILE_EXTENSIONS: [&str; 8] = ["opus", "webm", "mka", "mkv", "wma", "amr", "ac3", "ape"]; // need ffmpeg
 
-fn create_virtual_mic() -> SoundSystem {
+#[allow(unused_variables)]
+fn create_virtual_mic(routing_mode: RoutingMode, latency: LatencySettings) -> SoundSystem {
     #[cfg(target_os = "windows")]
     {
-        let (normal, virtual_mic) = windows_lib::create_virtual_mic_windows();
+        let (normal, virtual_mic, mic_route) = windows_lib::create_virtual_mic_windows(latency.ring_buffer_msec);
         return SoundSystem {
-            output_stream: virtual_mic,
-            normal_output_stream: normal,
+            normal_mixer: normal.mixer().clone(),
+            normal_output_stream: Some(normal),
+            mic_route,
+            ..SoundSystem::new(virtual_mic)
         };
     }
 
     #[cfg(target_os = "linux")]
     {
+        let (output_stream, routing_mode) = linux_lib::create_virtual_mic_linux(routing_mode, latency.loopback_latency_msec);
         return SoundSystem {
-            output_stream: linux_lib::create_virtual_mic_linux(),
+            routing_mode,
+            ..SoundSystem::new(output_stream)
         };
     }
 
@@ -88,31 +204,25 @@ fn create_virtual_mic() -> SoundSystem {
         let device = host
             .default_output_device()
             .expect("Could not get default output device");
-        SoundSystem {
-            output_stream: OutputStreamBuilder::from_device(device)
+        SoundSystem::new(
+            OutputStreamBuilder::from_device(device)
                 .expect("Unable to open device")
                 .open_stream()
                 .expect("Failed to open stream"),
-            // this is actually not needed here, since windows would exit by far. But, cargo doesnt like SoundSystem not getting the normal_output stream so...
-            #[cfg(target_os = "windows")]
-            normal_output_stream: OutputStreamBuilder::from_device(device)
-                .expect("Unable to open device")
-                .open_stream()
-                .expect("Failed to open stream"),
-        }
+        )
     }
 }
 
-fn reload_sound() -> SoundSystem {
+fn reload_sound(routing_mode: RoutingMode, latency: LatencySettings) -> SoundSystem {
     #[cfg(target_os = "linux")]
     linux_lib::reload_sound();
 
-    return create_virtual_mic();
+    return create_virtual_mic(routing_mode, latency);
 }
 
-fn list_outputs() -> Vec<(String, String)> {
+fn list_outputs() -> Vec<AppOutput> {
     #[cfg(target_os = "windows")]
-    return Vec::from([("Select inside apps".to_string(), String::from("9999999"))]);
+    return Vec::from([AppOutput { index: String::from("9999999"), app_name: "Select inside apps".to_string(), binary: String::new(), node_name: String::new(), source: String::new(), object_id: String::new() }]);
 
     #[cfg(target_os = "linux")]
     return linux_lib::list_outputs();
@@ -121,14 +231,91 @@ fn list_outputs() -> Vec<(String, String)> {
     return Vec::new();
 }
 
+fn new_app_state(sound_system: SoundSystem) -> AppState {
+    AppState {
+        loaded_files: HashMap::new(),
+        json_data: JSONData::default(),
+        current_directory: String::new(),
+        currently_playing: Vec::new(),
+        sound_system,
+        virt_outputs: Vec::new(),
+        applied_routing: HashMap::new(),
+        current_view: "main".to_string(),
+        last_virt_output_update: Instant::now(),
+        youtube_downloader_state: YoutubeDownloaderState { 
+            current_url: String::new(),
+            current_filename: String::new(),
+            download_directory: String::new(),
+            start_time: String::new(),
+            end_time: String::new(),
+            review_before_adding: false,
+            audio_settings: AudioSettings::default(),
+            overwrite_existing: false,
+            lookup: Arc::new(Mutex::new(LookupStatus::Idle)),
+            lookup_process: LookupProcess::default(),
+            url_edited: None,
+            video_info: None,
+            thumbnail: None,
+            batch_entries: Vec::new(),
+            auto_filename: String::new(),
+            queue: DownloadQueue::new(get_yt_dlp_path(), PathBuf::from("cache/downloads")),
+            preview_stream: None,
+            preview_sink: None
+        },
+        search_state: SearchState {
+            query: String::new(),
+            search_all_tabs: true,
+            selected_index: 0
+        },
+        sound_ids: HashMap::new(),
+        edited_sound: None,
+        play_history: Vec::new(),
+        notification: None,
+        transcoding: Arc::new(Mutex::new(Transcoding::default())),
+        hashing: Arc::new(Mutex::new(HashSet::new())),
+        hashed_sounds: Arc::new(Mutex::new(Vec::new())),
+        url_import_state: UrlImportState {
+            url: String::new(),
+            filename: String::new(),
+            download_directory: String::new(),
+            status: Arc::new(Mutex::new(UrlImportStatus::Idle))
+        },
+        yt_dlp_status: Arc::new(Mutex::new(YtDlpStatus::Unknown)),
+        ffmpeg_status: Arc::new(Mutex::new(FfmpegStatus::Unknown)),
+        routing_ui_state: RoutingUiState {
+            new_pattern: String::new(),
+            new_excluded_app: String::new()
+        },
+        latency_measurement: Arc::new(Mutex::new(LatencyMeasurement::Idle)),
+        volume: 1.0,
+        control_requests: Arc::new(Mutex::new(Vec::new())),
+        remote_server: None
+    }
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(run_cli(&args));
+    }
+
+    // bound before the virtual mic is set up, a second window would load the pactl modules twice
+    let control_listener = match bind_control_socket(&control_socket_name()) {
+        Ok(listener) => Some(listener),
+        Err(err) if err.kind() == std::io::ErrorKind::AddrInUse => {
+            println!("The soundboard is already running.");
+            return;
+        }
+        Err(err) => {
+            println!("Could not open the control socket: {}", err);
+            None
+        }
+    };
+
     if !exists("bin").expect("Could not check existence of bin folder") {
         let _ = create_dir("bin");
     }
 
-    check_and_download_ffmpeg();
-    check_and_download_yt_dlp();
-
     App::new()
         .insert_resource(ClearColor(Color::BLACK))
         .add_plugins(
@@ -148,53 +335,62 @@ fn main() {
                 }),
         )
         .add_plugins(bevy_egui::EguiPlugin::default())
-        .insert_resource(AppState {
-            loaded_files: HashMap::new(),
-            json_data: JSONData { tabs: Vec::new() },
-            current_directory: String::new(),
-            currently_playing: Vec::new(),
-            sound_system: create_virtual_mic(),
-            virt_outputs: Vec::new(),
-            is_virt_output_used: HashMap::new(),
-            current_view: "main".to_string(),
-            last_virt_output_update: Instant::now(),
-            youtube_downloader_state: YoutubeDownloaderState { 
-                current_url: String::new(),
-                current_filename: String::new(),
-                download_directory: String::new(),
-                yt_dlp_running: false,
-                yt_dlp_stdout_text: Arc::new(Mutex::new(String::new()))
+        .insert_resource({
+            let json_data = read_json_data().unwrap_or_default();
+            let app_state = new_app_state(create_virtual_mic(json_data.routing_mode, json_data.latency));
+            if let Some(listener) = control_listener {
+                start_control_server(listener, &app_state.control_requests); // requests wait in the queue until the first update
             }
+            app_state
         })
         .add_systems(
             PreStartup,
             setup_camera_system.before(EguiStartupSet::InitContexts),
         )
         .add_systems(Startup, load_system)
+        .add_systems(Update, file_drop_system)
         .add_systems(
             EguiPrimaryContextPass,
             (draw, update_ui_scale_factor_system, update),
         )
+        .add_systems(Last, record_plays_on_exit)
         .run();
 }
 
+// sounds still playing when the window closes would otherwise never reach stats.jsonl
+fn record_plays_on_exit(mut exits: MessageReader<AppExit>, mut app_state: ResMut<AppState>) {
+    if exits.read().next().is_some() {
+        stop_all_sounds(&mut app_state);
+    }
+}
+
 fn update(mut app_state: ResMut<AppState>) {
+    poll_control_requests(&mut app_state);
+    app_state.youtube_downloader_state.queue.process();
+    if app_state.youtube_downloader_state.queue.library_changed.swap(false, std::sync::atomic::Ordering::SeqCst) {
+        load_data(&mut app_state);
+    }
+    if collect_sound_ids(&mut app_state) {
+        start_transcoding(&app_state); // the cached transcodes are named after the hash
+    }
+
     #[cfg(target_os = "linux")] {
         if app_state.last_virt_output_update.elapsed().as_secs_f32() >= 1.5 {
             app_state.last_virt_output_update = Instant::now();
-            app_state.virt_outputs = list_outputs();
-            let is_virt_output_used = app_state.is_virt_output_used.clone();
-
-            for virt_output in &app_state.virt_outputs.clone() {
-                if !is_virt_output_used.contains_key(&virt_output.1) {
-                    app_state.is_virt_output_used.insert(virt_output.1.clone(), false);
-                }
+            app_state.virt_outputs = list_outputs().into_iter().filter(|output| !is_excluded(&app_state.json_data.exclusions, output)).collect();
 
-                if app_state.is_virt_output_used[&virt_output.1] {
-                    linux_lib::move_output_to_sink(virt_output.1.clone(), linux_lib::get_soundboard_sink_index());
+            // rules are keyed by app, so they also apply to apps that restarted and got a new index
+            if app_state.sound_system.routing_mode == RoutingMode::PipewireLinks {
+                let app_state = &mut *app_state;
+                for (output, linked) in plan_links(&mut app_state.applied_routing, &app_state.virt_outputs, &app_state.json_data.routing_rules) {
+                    linux_lib::set_soundboard_link(&output.object_id, linked);
                 }
-                else {
-                    linux_lib::move_output_to_sink(virt_output.1.clone(), linux_lib::get_default_source());
+            }
+            else if let Some(virtual_mic_source) = linux_lib::get_virtual_mic_source_index() {
+                let app_state = &mut *app_state;
+                let moves = plan_routing(&mut app_state.applied_routing, &app_state.virt_outputs, &app_state.json_data.routing_rules, &virtual_mic_source, &linux_lib::get_default_source());
+                for routing_move in moves {
+                    linux_lib::move_output_to_sink(routing_move.output_index, routing_move.source);
                 }
             }
         }
@@ -203,50 +399,93 @@ fn update(mut app_state: ResMut<AppState>) {
 
 fn load_system(mut app_state: ResMut<AppState>) {   
     load_data(&mut app_state);
+    app_state.play_history = load_play_history();
+
+    start_remote_if_enabled(&mut app_state);
+
+    let settings = app_state.json_data.yt_dlp.clone();
+    app_state.youtube_downloader_state.queue.yt_dlp_path = configured_yt_dlp_path(&settings);
+
+    if !app_state.json_data.setup.completed {
+        open_setup(&mut app_state);
+    }
+    // runs in the background, a missing binary is downloaded even with auto updates off
+    else if app_state.json_data.setup.youtube_enabled && (settings.use_system || settings.auto_update || !Path::new(&get_yt_dlp_path()).exists()) {
+        start_yt_dlp_update(&settings, &app_state.yt_dlp_status);
+    }
+}
+
+fn read_json_data() -> Option<JSONData> {
+    if !std::fs::exists("data.json").expect("Failed to check existence of JSON file") {
+        return None;
+    }
+
+    let data = std::fs::read_to_string("data.json").expect("Failed to read JSON");
+    Some(serde_json::from_str(&data).expect("Failed to load JSON"))
 }
 
 fn load_data(app_state: &mut AppState) {
-    if std::fs::exists("data.json").expect("Failed to check existence of JSON file") {
-        let data = std::fs::read_to_string("data.json").expect("Failed to read JSON");
-        app_state.json_data = serde_json::from_str(&data).expect("Failed to load JSON");
+    if let Some(json_data) = read_json_data() {
+        app_state.json_data = json_data;
 
         let tabs = app_state.json_data.tabs.clone();
-        app_state.loaded_files.clear();
 
-        if tabs.len() > 0 {
+        // keep the selected tab across reloads, unless it was removed
+        if tabs.len() > 0 && !tabs.contains(&app_state.current_directory) && !VIRTUAL_TABS.contains(&app_state.current_directory.as_str()) {
             app_state.current_directory = tabs[0].clone();
         }
 
-        for tab in tabs {
-            app_state.loaded_files.insert(tab.clone(), Vec::new());
-            if std::fs::exists(tab.clone()).expect("Failed to check existence of tab directory.") {
-                app_state.loaded_files.insert(
-                    tab.clone(),
-                    std::fs::read_dir(tab)
-                        .expect("Failed to read directory")
-                        .filter_map(|entry| {
-                            entry.ok().and_then(|e| {
-                                let path = e.path();
-                                if path.is_file()
-                                    && ALLOWED_FILE_EXTENSIONS.contains(
-                                        &path
-                                            .extension()
-                                            .unwrap_or_default()
-                                            .to_str()
-                                            .expect("Could not convert extension to string"),
-                                    )
-                                {
-                                    path.to_str().map(|s| s.to_string())
-                                } else {
-                                    None
-                                }
-                            })
+        app_state.loaded_files = load_library(&tabs);
+
+        load_sound_ids(app_state);
+        start_transcoding(app_state);
+    }
+}
+
+// every tab with the sounds in it, tabs whose folder is gone stay in the list but are empty
+fn load_library(tabs: &[String]) -> HashMap<String, Vec<String>> {
+    let mut loaded_files = HashMap::new();
+
+    for tab in tabs {
+        loaded_files.insert(tab.clone(), Vec::new());
+        if std::fs::exists(tab).expect("Failed to check existence of tab directory.") {
+            loaded_files.insert(
+                tab.clone(),
+                std::fs::read_dir(tab)
+                    .expect("Failed to read directory")
+                    .filter_map(|entry| {
+                        entry.ok().and_then(|e| {
+                            let path = e.path();
+                            if path.is_file() && is_allowed_sound_file(&path) {
+                                path.to_str().map(|s| s.to_string())
+                            } else {
+                                None
+                            }
                         })
-                        .collect(),
-                );
-            }
+                    })
+                    .collect(),
+            );
         }
     }
+
+    loaded_files
+}
+
+fn is_allowed_sound_file(path: &Path) -> bool {
+    detect_format(path).is_some()
+}
+
+fn save_data(app_state: &AppState) {
+    write_json_data(&app_state.json_data);
+}
+
+fn write_json_data(json_data: &JSONData) {
+    std::fs::write(
+        "data.json",
+        serde_json::to_string(json_data)
+            .expect("Could not convert JSON to string"),
+    )
+    .expect("Could not write to JSON file");
 }
 
 fn setup_camera_system(mut commands: Commands) {
@@ -274,15 +513,43 @@ where
     total_samples as f32 / (sample_rate * channels) as f32
 }
 
-fn play_sound(file_path: String, app_state: &mut AppState) {
-    let file = File::open(&file_path).unwrap();
-    let mut src = Decoder::new(BufReader::new(file)).unwrap();
+fn open_decoder(file_path: &str) -> Result<Decoder<BufReader<File>>, String> {
+    let file = File::open(file_path).map_err(|err| format!("Could not open {}: {}", file_path, err))?;
+    Decoder::new(BufReader::new(file)).map_err(|err| format!("Could not decode {}: {}", file_path, err))
+}
+
+fn play_sound(file_path: String, trigger: PlayTrigger, app_state: &mut AppState) {
+    let Some(playable_path) = playable_path(app_state, &file_path) else {
+        let converting = app_state.transcoding.lock().expect("Transcoding lock poisoned").is_converting(&file_path)
+            || app_state.hashing.lock().expect("Hashing lock poisoned").contains(&file_path);
+        let message = if converting {
+            format!("{} is still being converted, try again in a moment.", file_path)
+        }
+        else if app_state.transcoding.lock().expect("Transcoding lock poisoned").has_failed(&sound_id(app_state, &file_path)) {
+            format!("{} could not be converted, FFmpeg does not understand it.", file_path)
+        }
+        else {
+            format!("{} can only be played after converting it, which needs FFmpeg.", file_path)
+        };
+        notify(app_state, message);
+        return;
+    };
+
+    let mut src = match open_decoder(&playable_path) {
+        Ok(src) => src,
+        Err(err) => {
+            notify(app_state, err);
+            return;
+        }
+    };
     let length = get_duration(&mut src);
     
     // need to recreate since get_duration seeks to the end and nothing is left
-    let file = File::open(&file_path).unwrap();
-    let src = Decoder::new(BufReader::new(file)).unwrap();
-    let sink = Sink::connect_new(&app_state.sound_system.output_stream.mixer());
+    let Ok(src) = open_decoder(&playable_path) else {
+        return;
+    };
+    let sink = Sink::connect_new(&app_state.sound_system.mixer);
+    sink.set_volume(app_state.volume);
     sink.append(src);
     sink.play();
 
@@ -291,12 +558,14 @@ fn play_sound(file_path: String, app_state: &mut AppState) {
         length,
         sink,
         to_remove: false,
+        started_at: unix_timestamp(),
+        trigger,
         #[cfg(target_os = "windows")]
         normal_sink: {
-            let file2 = File::open(&file_path).unwrap();
-            let src2 = Decoder::new(BufReader::new(file2)).unwrap();
+            let src2 = open_decoder(&playable_path).expect("Sound file disappeared while opening it");
             let normal_sink =
-                Sink::connect_new(&app_state.sound_system.normal_output_stream.mixer());
+                Sink::connect_new(&app_state.sound_system.normal_mixer);
+            normal_sink.set_volume(app_state.volume);
             normal_sink.append(src2);
             normal_sink.play();
             normal_sink
@@ -304,23 +573,42 @@ fn play_sound(file_path: String, app_state: &mut AppState) {
     };
 
     app_state.currently_playing.push(playing_sound);
+    add_to_recent(app_state, &file_path);
+}
+
+fn stop_all_sounds(app_state: &mut AppState) {
+    for playing_sound in std::mem::take(&mut app_state.currently_playing) {
+        record_play(app_state, &playing_sound);
+    }
 }
 
 fn create_virtual_mic_ui(ui: &mut Ui, app_state: &mut ResMut<AppState>, available_width: f32, available_height: f32) {
     #[cfg(target_os = "linux")] {
-        if app_state.is_virt_output_used.len() != 0 {
+        if !app_state.virt_outputs.is_empty() {
             let outputs = app_state.virt_outputs.clone();
             for output in &outputs {
-                let current_value = *app_state.is_virt_output_used.get(&output.1).unwrap_or(&false);
-                if ui
-                    .add_sized(
-                        [available_width, available_height / 30.0],
-                        egui::Button::new(format!("{} - {}", output.0.clone(), current_value)),
-                    )
-                    .clicked()
-                {
-                    *app_state.is_virt_output_used.entry(output.1.clone()).or_insert(false) = !current_value;
-                }
+                let current_value = is_routed(&app_state.json_data.routing_rules, output);
+                ui.horizontal(|ui| {
+                    let hide_width = available_width * 0.2;
+                    if ui
+                        .add_sized(
+                            [available_width - hide_width - ui.spacing().item_spacing.x, available_height / 30.0],
+                            egui::Button::new(format!("{} - {}", output.label(), current_value)),
+                        )
+                        .clicked()
+                    {
+                        toggle_routing(&mut app_state.json_data.routing_rules, output);
+                        save_data(app_state);
+                    }
+
+                    if ui
+                        .add_sized([hide_width, available_height / 30.0], egui::Button::new("Hide"))
+                        .on_hover_text("Hide this app from the list")
+                        .clicked()
+                    {
+                        hide_app(app_state, output);
+                    }
+                });
             }
         }
         else {
@@ -336,6 +624,19 @@ fn create_virtual_mic_ui(ui: &mut Ui, app_state: &mut ResMut<AppState>, availabl
 }
 
 fn main_ui(ctx: &Context, mut app_state: ResMut<AppState>) {
+    // bevy_egui turns ctrl+v into a text event, only take it if no text field would get it
+    let pasted: Vec<String> = ctx.input(|i| {
+        if !(i.modifiers.command && i.key_pressed(egui::Key::V)) {
+            return Vec::new();
+        }
+        i.events.iter().filter_map(|event| if let egui::Event::Text(text) = event { Some(text.clone()) } else { None }).collect()
+    });
+    if ctx.memory(|memory| memory.focused().is_none()) {
+        for text in pasted {
+            import_pasted_text(&mut app_state, &text);
+        }
+    }
+
     egui::SidePanel::right("tools").show(ctx, |ui| {
         ui.heading("Tools");
 
@@ -346,6 +647,26 @@ fn main_ui(ctx: &Context, mut app_state: ResMut<AppState>) {
         ui.label("Virtual Mic Output");
         create_virtual_mic_ui(ui, &mut app_state, available_width, available_height);
 
+        if ui
+            .add_sized(
+                [available_width, available_height / 15.0],
+                egui::Button::new("App routing"),
+            )
+            .clicked()
+        {
+            app_state.current_view = "routing".to_string();
+        }
+
+        if ui
+            .add_sized(
+                [available_width, available_height / 15.0],
+                egui::Button::new("Remote control"),
+            )
+            .clicked()
+        {
+            app_state.current_view = "remote".to_string();
+        }
+
         if ui
             .add_sized(
                 [available_width, available_height / 15.0],
@@ -354,18 +675,7 @@ fn main_ui(ctx: &Context, mut app_state: ResMut<AppState>) {
             .clicked()
         {
             if let Some(folder) = rfd::FileDialog::new().pick_folder() {
-                if let Some(path_str) = folder.to_str() {
-                    app_state.json_data.tabs.push(path_str.to_string());
-                    std::fs::write(
-                        "data.json",
-                        serde_json::to_string(&app_state.json_data)
-                            .expect("Could not convert JSON to string"),
-                    )
-                    .expect("Could not write to JSON file");
-                    load_data(&mut app_state);
-                } else {
-                    println!("Invalid path encoding!");
-                }
+                add_tab(&mut app_state, &folder);
             }
         }
 
@@ -380,7 +690,7 @@ fn main_ui(ctx: &Context, mut app_state: ResMut<AppState>) {
             println!("Reloaded content");
         }
 
-        if ui
+        if app_state.json_data.setup.youtube_enabled && ui
             .add_sized(
                 [available_width, available_height / 15.0],
                 egui::Button::new("Youtube downloader"),
@@ -390,6 +700,37 @@ fn main_ui(ctx: &Context, mut app_state: ResMut<AppState>) {
             app_state.current_view = "youtube_downloader".to_string();
         }
 
+        if ui
+            .add_sized(
+                [available_width, available_height / 15.0],
+                egui::Button::new("Import from URL"),
+            )
+            .clicked()
+        {
+            app_state.url_import_state.download_directory = app_state.current_directory.clone();
+            app_state.current_view = "url_import".to_string();
+        }
+
+        if ui
+            .add_sized(
+                [available_width, available_height / 15.0],
+                egui::Button::new("Play statistics"),
+            )
+            .clicked()
+        {
+            app_state.current_view = "stats".to_string();
+        }
+
+        if ui
+            .add_sized(
+                [available_width, available_height / 15.0],
+                egui::Button::new("Setup"),
+            )
+            .clicked()
+        {
+            open_setup(&mut app_state);
+        }
+
         if ui
             .add_sized(
                 [available_width, available_height / 15.0],
@@ -397,8 +738,9 @@ fn main_ui(ctx: &Context, mut app_state: ResMut<AppState>) {
             )
             .clicked()
         {
-            app_state.currently_playing.clear();
-            app_state.sound_system = reload_sound();
+            stop_all_sounds(&mut app_state);
+            app_state.sound_system = reload_sound(app_state.json_data.routing_mode, app_state.json_data.latency);
+            app_state.applied_routing.clear(); // the old virtual mic and links are gone
             println!("Sucessfully reloaded sound system!");
         }
     });
@@ -407,7 +749,11 @@ fn main_ui(ctx: &Context, mut app_state: ResMut<AppState>) {
         let available_height = ui.available_height();
         ui.horizontal(|ui| {
             let available_width = ui.available_width();
-            let current_directories = app_state.loaded_files.keys().cloned().collect::<Vec<_>>();
+            let current_directories = VIRTUAL_TABS
+                .iter()
+                .map(|tab| tab.to_string())
+                .chain(app_state.loaded_files.keys().cloned())
+                .collect::<Vec<_>>();
             for directory in current_directories.clone() {
                 let mut button = egui::Button::new(&directory);
                 if directory == app_state.current_directory {
@@ -429,121 +775,80 @@ fn main_ui(ctx: &Context, mut app_state: ResMut<AppState>) {
             }
         });
         ui.add_space(available_height / 50.0);
-        if app_state.current_directory.chars().count() > 0 {
-            let files = app_state
-                .loaded_files
-                .get(&app_state.current_directory)
-                .cloned()
-                .unwrap_or_default();
 
-            egui::ScrollArea::vertical().show(ui, |ui| {
-                for element in files {
-                    if let Some(filename) = element.split("/").collect::<Vec<_>>().last() {
-                        if ui
-                            .add_sized(
-                                [ui.available_width(), available_height / 15.0],
-                                egui::Button::new(*filename),
-                            )
-                            .clicked()
-                        {
-                            let path = Path::new(&app_state.current_directory)
-                                .join(filename)
-                                .to_string_lossy()
-                                .to_string();
-                            play_sound(path, &mut app_state);
-                        }
-                    }
-                }
-            });
+        let mut search_response = None;
+        ui.horizontal(|ui| {
+            let checkbox_width = ui.available_width() / 5.0;
+            search_response = Some(ui.add_sized(
+                [ui.available_width() - checkbox_width, available_height / 20.0],
+                egui::TextEdit::singleline(&mut app_state.search_state.query).hint_text("Search sounds..."),
+            ));
+            ui.checkbox(&mut app_state.search_state.search_all_tabs, "Search all tabs");
+        });
+        ui.add_space(available_height / 50.0);
+
+        if search_response.as_ref().is_some_and(|response| response.changed()) {
+            app_state.search_state.selected_index = 0;
         }
-    });
-}
 
-fn download_youtube_sound(app_state: &mut ResMut<AppState>) {
-    let filename = app_state.youtube_downloader_state.current_filename.clone();
-    let download_directory = app_state.youtube_downloader_state.download_directory.clone();
-    let current_url = app_state.youtube_downloader_state.current_url.clone();
-    let stdout_text = Arc::clone(&app_state.youtube_downloader_state.yt_dlp_stdout_text);
-
-    app_state.youtube_downloader_state.yt_dlp_running = true;
-
-    thread::spawn(move || {
-        let mut command = Command::new(get_yt_dlp_path())
-            .args(&["-x", "--audio-format", "mp3", "-o", "sound.mp3", current_url.as_str()]) 
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .expect("Failed to execute process");
-
-        if let Some(mut stdout) = command.stdout.take() {
-            let mut buffer = String::new();
-            loop {
-                let mut chunk = vec![0u8; 1024];
-                match stdout.read(&mut chunk) {
-                    Ok(0) => break,
-                    Ok(n) => {
-                        if let Ok(text) = String::from_utf8(chunk[..n].to_vec()) {
-                            buffer.push_str(&text);
-                            if let Ok(mut locked) = stdout_text.lock() {
-                                *locked = buffer.clone();
-                            }
-                        }
-                    }
-                    Err(_) => break,
+        if !app_state.search_state.query.trim().is_empty() {
+            let results = search_sounds(&app_state);
+            let (down_pressed, up_pressed, enter_pressed) = ui.input(|i| {
+                (i.key_pressed(egui::Key::ArrowDown), i.key_pressed(egui::Key::ArrowUp), i.key_pressed(egui::Key::Enter))
+            });
+
+            if down_pressed && app_state.search_state.selected_index + 1 < results.len() {
+                app_state.search_state.selected_index += 1;
+            }
+            if up_pressed && app_state.search_state.selected_index > 0 {
+                app_state.search_state.selected_index -= 1;
+            }
+            app_state.search_state.selected_index = app_state.search_state.selected_index.min(results.len().saturating_sub(1));
+
+            if enter_pressed {
+                if let Some((_, path)) = results.get(app_state.search_state.selected_index) {
+                    play_sound(path.clone(), PlayTrigger::Hotkey, &mut app_state);
+                }
+                if let Some(search_response) = &search_response {
+                    search_response.request_focus(); // singleline text edits lose focus on enter, keep typing possible
                 }
             }
-        }
-        let _ = command.wait();
 
-        let path = Path::new(&download_directory).join(filename);
-        let _ = rename("sound.mp3", path.to_string_lossy().as_str());
-    });
-}
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                if results.is_empty() {
+                    ui.label("No sounds found.");
+                }
 
-fn youtube_downloader_ui(ctx: &Context, mut app_state: ResMut<AppState>) {
-    egui::CentralPanel::default().show(ctx, |ui| {
-        let available_width = ui.available_width();
-        let available_height = ui.available_height();
-        
-        ui.with_layout(egui::Layout::top_down(egui::Align::Center), |ui| {
-            ui.heading("Directory");
-            egui::ComboBox::from_id_salt("Download Directory Selector")
-                .selected_text(app_state.youtube_downloader_state.download_directory.clone())
-                .width(available_width)
-                .height(available_height / 15.0)
-                .show_ui(ui, |ui| {
-                    for directory in &app_state.loaded_files.keys().cloned().collect::<Vec<_>>() {
-                        ui.selectable_value(
-                            &mut app_state.youtube_downloader_state.download_directory,
-                            directory.clone(),
-                            directory,
-                        );
-                    }
-                });
+                for (index, (tab, path)) in results.iter().enumerate() {
+                    let display_name = sound_display_name(&app_state, path);
+                    let text = if app_state.search_state.search_all_tabs { format!("{} ({})", display_name, tab) } else { display_name };
+                    let selected = index == app_state.search_state.selected_index;
 
-            ui.heading("Filename");
-            ui.add_sized([available_width, available_height / 20.0], egui::TextEdit::singleline(&mut app_state.youtube_downloader_state.current_filename));
+                    let response = sound_button(ui, &mut app_state, path, text, [ui.available_width(), available_height / 15.0], selected, false);
+                    if index == app_state.search_state.selected_index && (down_pressed || up_pressed) {
+                        response.scroll_to_me(None);
+                    }
+                    if response.clicked() {
+                        app_state.search_state.selected_index = index;
+                        play_sound(path.clone(), PlayTrigger::Click, &mut app_state);
+                    }
+                }
+            });
+        }
+        else if app_state.current_directory.chars().count() > 0 {
+            let mut files = tab_files(&app_state, &app_state.current_directory);
+            if app_state.json_data.sort_by_play_count {
+                let counts = play_counts(&app_state);
+                files.sort_by_key(|file_path| std::cmp::Reverse(play_count(&counts, &app_state, file_path)));
+            }
 
-            ui.heading("Youtube URL");
-            ui.add_sized([available_width, available_height / 20.0], egui::TextEdit::singleline(&mut app_state.youtube_downloader_state.current_url));
-        });
-        
-        if let Ok(text) = app_state.youtube_downloader_state.yt_dlp_stdout_text.lock() {
-            ui.colored_label(Color32::GREEN, text.clone());
-        };
+            layout_settings_ui(ui, &mut app_state);
+            ui.add_space(available_height / 50.0);
 
-        if ui
-            .add_sized(
-                [
-                    available_width as f32,
-                    available_height / 15.0,
-                ],
-                egui::Button::new("Download Sound"),
-            )
-            .clicked()
-        {
-            download_youtube_sound(&mut app_state);
-        };
+            if let Some(clicked) = sound_grid_ui(ui, &mut app_state, &files) {
+                play_sound(clicked, PlayTrigger::Click, &mut app_state);
+            }
+        }
     });
 }
 
@@ -572,6 +877,10 @@ fn draw(mut contexts: EguiContexts, mut app_state: ResMut<AppState>) -> Result {
         else {
             ui.heading("csd4ni3l Soundboard");
         }
+
+        if let Some((message, shown_at)) = &app_state.notification && shown_at.elapsed().as_secs_f32() < 5.0 {
+            ui.colored_label(Color32::YELLOW, message);
+        }
     });
 
     let window_height = ctx.content_rect().height();
@@ -580,11 +889,12 @@ fn draw(mut contexts: EguiContexts, mut app_state: ResMut<AppState>) -> Result {
         .exact_height(window_height * 0.1)
         .show(ctx, |ui| {
         ui.vertical(|ui| {
-            for playing_sound in &mut app_state.currently_playing {
+            let display_names: Vec<String> = app_state.currently_playing.iter().map(|playing_sound| sound_display_name(&app_state, &playing_sound.file_path)).collect();
+            for (playing_sound, display_name) in app_state.currently_playing.iter_mut().zip(display_names) {
                 ui.horizontal(|ui| {
                     ui.label(format!(
                         "{} - {:.2} / {:.2}",
-                        playing_sound.file_path,
+                        display_name,
                         playing_sound.sink.get_pos().as_secs_f32(),
                         playing_sound.length
                     ));
@@ -633,20 +943,41 @@ fn draw(mut contexts: EguiContexts, mut app_state: ResMut<AppState>) -> Result {
             )
             .clicked()
         {
-            app_state.currently_playing.clear();
+            stop_all_sounds(&mut app_state);
         }
     });
     
-    app_state.currently_playing.retain(|playing_sound| { // retains happen the next cycle, not in the current one because of borrowing and im lazy to fix
-        playing_sound.sink.get_pos().as_secs_f32() <= (playing_sound.length - 0.01) && !playing_sound.to_remove  // 0.01 offset needed here because of floating point errors and so its not exact
-    });
+    let finished_sounds: Vec<PlayingSound> = app_state.currently_playing.extract_if(.., |playing_sound| { // removals happen the next cycle, not in the current one because of borrowing and im lazy to fix
+        playing_sound.sink.get_pos().as_secs_f32() > (playing_sound.length - 0.01) || playing_sound.to_remove  // 0.01 offset needed here because of floating point errors and so its not exact
+    }).collect();
+    for playing_sound in finished_sounds {
+        record_play(&mut app_state, &playing_sound);
+    }
     
+    edit_sound_ui(ctx, &mut app_state);
+    poll_url_import(&mut app_state);
+
     if app_state.current_view == "main".to_string() {
         main_ui(ctx, app_state);
     }
     else if app_state.current_view == "youtube_downloader".to_string() {
         youtube_downloader_ui(ctx, app_state);
     }
+    else if app_state.current_view == "stats" {
+        stats_ui(ctx, app_state);
+    }
+    else if app_state.current_view == "url_import" {
+        url_import_ui(ctx, app_state);
+    }
+    else if app_state.current_view == "setup" {
+        setup_ui(ctx, app_state);
+    }
+    else if app_state.current_view == "routing" {
+        routing_ui(ctx, app_state);
+    }
+    else if app_state.current_view == "remote" {
+        remote_ui(ctx, app_state);
+    }
 
     Ok(())
 }
@@ -0,0 +1,354 @@
+use std::{collections::{HashMap, HashSet}, fs, path::Path, sync::Arc, thread, time::UNIX_EPOCH};
+
+use bevy_egui::egui::{self, Color32, Context, Response, Ui};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{AppState, save_data, virtual_tabs::{is_favorite, toggle_favorite}};
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct SoundMetadata {
+    pub display_name: String,
+    pub tags: Vec<String>,
+    pub color: Option<[u8; 3]>,
+    pub icon: String,
+    pub notes: String,
+}
+
+pub struct EditSoundState {
+    pub sound_id: String,
+    pub file_path: String,
+    pub metadata: SoundMetadata,
+    pub tags_text: String,
+}
+
+const HASH_CACHE_FILE: &str = "cache/hashes.json";
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct CachedHash {
+    size: u64,
+    modified: u128,
+    hash: String,
+}
+
+// metadata is keyed by the content hash, so it survives moving and renaming the file
+pub fn hash_file(file_path: &str) -> Option<String> {
+    let content = fs::read(file_path).ok()?;
+    Some(format!("{:x}", Sha256::digest(&content)))
+}
+
+fn file_stamp(file_path: &str) -> Option<(u64, u128)> {
+    let metadata = fs::metadata(file_path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos();
+    Some((metadata.len(), modified))
+}
+
+fn hash_with_stamp(file_path: &str) -> Option<CachedHash> {
+    let (size, modified) = file_stamp(file_path)?;
+    Some(CachedHash { size, modified, hash: hash_file(file_path)? })
+}
+
+fn load_hash_cache() -> HashMap<String, CachedHash> {
+    fs::read_to_string(HASH_CACHE_FILE).ok().and_then(|data| serde_json::from_str(&data).ok()).unwrap_or_default()
+}
+
+fn save_hash_cache(cache: &HashMap<String, CachedHash>) {
+    let _ = fs::create_dir_all("cache");
+    let _ = fs::write(HASH_CACHE_FILE, serde_json::to_string(cache).expect("Could not convert hash cache to JSON"));
+}
+
+// a cached hash is only trusted while the file keeps its size and modification time
+fn cached_hash(cache: &HashMap<String, CachedHash>, file_path: &str) -> Option<String> {
+    let (size, modified) = file_stamp(file_path)?;
+    cache.get(file_path).filter(|cached| cached.size == size && cached.modified == modified).map(|cached| cached.hash.clone())
+}
+
+// takes the ids that are already cached and returns the sounds that still have to be hashed
+pub fn load_cached_sound_ids(app_state: &mut AppState) -> Vec<String> {
+    let cache = load_hash_cache();
+    let mut missing = Vec::new();
+
+    app_state.sound_ids.clear();
+    for file_path in app_state.loaded_files.values().flatten() {
+        match cached_hash(&cache, file_path) {
+            Some(hash) => { app_state.sound_ids.insert(file_path.clone(), hash); }
+            None => missing.push(file_path.clone()),
+        }
+    }
+
+    missing
+}
+
+// the sounds that aren't cached yet are hashed in the background and picked up by collect_sound_ids
+pub fn load_sound_ids(app_state: &mut AppState) {
+    let mut missing = load_cached_sound_ids(app_state);
+
+    let hashing = Arc::clone(&app_state.hashing);
+    missing.retain(|file_path| hashing.lock().expect("Hashing lock poisoned").insert(file_path.clone()));
+    if missing.is_empty() {
+        return;
+    }
+
+    let hashed_sounds = Arc::clone(&app_state.hashed_sounds);
+    thread::spawn(move || {
+        for file_path in missing {
+            if let Some(hashed) = hash_with_stamp(&file_path) {
+                hashed_sounds.lock().expect("Hashing lock poisoned").push((file_path.clone(), hashed));
+            }
+            hashing.lock().expect("Hashing lock poisoned").remove(&file_path);
+        }
+    });
+}
+
+// edits, favorites and recents made while a sound was still keyed by its path move over to the hash, returns whether any did
+fn adopt_sound_id(app_state: &mut AppState, file_path: &str, hash: &str) -> bool {
+    app_state.sound_ids.insert(file_path.to_string(), hash.to_string());
+
+    let json_data = &mut app_state.json_data;
+    let mut moved = false;
+    if let Some(metadata) = json_data.sound_metadata.remove(file_path) {
+        json_data.sound_metadata.insert(hash.to_string(), metadata); // saved after the hash entry, so it is the newer one
+        moved = true;
+    }
+
+    for sound_ids in [&mut json_data.favorites, &mut json_data.recent] {
+        if sound_ids.iter().any(|sound_id| sound_id == file_path) {
+            sound_ids.iter_mut().filter(|sound_id| *sound_id == file_path).for_each(|sound_id| *sound_id = hash.to_string());
+            let mut seen = HashSet::new();
+            sound_ids.retain(|sound_id| seen.insert(sound_id.clone()));
+            moved = true;
+        }
+    }
+
+    // an editor opened before the hash arrived would otherwise save under the path again
+    if let Some(edited_sound) = &mut app_state.edited_sound && edited_sound.sound_id == file_path {
+        edited_sound.sound_id = hash.to_string();
+    }
+
+    moved
+}
+
+// returns whether new ids arrived
+pub fn collect_sound_ids(app_state: &mut AppState) -> bool {
+    let hashed: Vec<(String, CachedHash)> = app_state.hashed_sounds.lock().expect("Hashing lock poisoned").drain(..).collect();
+    if hashed.is_empty() {
+        return false;
+    }
+
+    let mut cache = load_hash_cache();
+    let mut moved = false;
+    for (file_path, hashed) in hashed {
+        moved |= adopt_sound_id(app_state, &file_path, &hashed.hash);
+        cache.insert(file_path, hashed);
+    }
+
+    // forget files that left the library
+    cache.retain(|file_path, _| app_state.loaded_files.values().flatten().any(|loaded| loaded == file_path));
+    save_hash_cache(&cache);
+    if moved {
+        save_data(app_state);
+    }
+    true
+}
+
+// hashes a single sound right away, for the headless commands which don't hash the whole library
+pub fn lookup_sound_id(app_state: &mut AppState, file_path: &str) {
+    let mut cache = load_hash_cache();
+    let hash = match cached_hash(&cache, file_path) {
+        Some(hash) => hash,
+        None => {
+            let Some(hashed) = hash_with_stamp(file_path) else {
+                return;
+            };
+            let hash = hashed.hash.clone();
+            cache.insert(file_path.to_string(), hashed);
+            save_hash_cache(&cache);
+            hash
+        }
+    };
+
+    if adopt_sound_id(app_state, file_path, &hash) {
+        save_data(app_state);
+    }
+}
+
+// falls back to the path until the hash is ready
+pub fn sound_id(app_state: &AppState, file_path: &str) -> String {
+    app_state.sound_ids.get(file_path).cloned().unwrap_or_else(|| file_path.to_string())
+}
+
+pub fn get_sound_metadata<'a>(app_state: &'a AppState, file_path: &str) -> Option<&'a SoundMetadata> {
+    app_state.json_data.sound_metadata.get(&sound_id(app_state, file_path))
+}
+
+pub fn filename(file_path: &str) -> String {
+    Path::new(file_path).file_name().unwrap_or_default().to_string_lossy().to_string()
+}
+
+pub fn sound_display_name(app_state: &AppState, file_path: &str) -> String {
+    let Some(metadata) = get_sound_metadata(app_state, file_path) else {
+        return filename(file_path);
+    };
+
+    let name = if metadata.display_name.trim().is_empty() { filename(file_path) } else { metadata.display_name.clone() };
+
+    if metadata.icon.trim().is_empty() {
+        name
+    }
+    else {
+        format!("{} {}", metadata.icon.trim(), name)
+    }
+}
+
+pub fn search_candidates(app_state: &AppState, file_path: &str) -> Vec<String> { // everything the search box matches against
+    let mut candidates = vec![filename(file_path)];
+
+    if let Some(metadata) = get_sound_metadata(app_state, file_path) {
+        candidates.push(metadata.display_name.clone());
+        candidates.extend(metadata.tags.iter().cloned());
+    }
+
+    candidates
+}
+
+pub fn sound_button(ui: &mut Ui, app_state: &mut AppState, file_path: &str, text: String, size: [f32; 2], selected: bool, wrap_text: bool) -> Response {
+    let mut button = egui::Button::new(text);
+    button = if wrap_text { button.wrap() } else { button.truncate() };
+    if selected {
+        button = button.fill(Color32::BLACK);
+    }
+    else if let Some([r, g, b]) = get_sound_metadata(app_state, file_path).and_then(|metadata| metadata.color) {
+        button = button.fill(Color32::from_rgb(r, g, b));
+    }
+
+    let star_size = [size[1].min(32.0), size[1]];
+    let response = ui.horizontal(|ui| {
+        let favorite = is_favorite(app_state, file_path);
+        if ui.add_sized(star_size, egui::Button::new(if favorite { "★" } else { "☆" })).clicked() {
+            toggle_favorite(app_state, file_path);
+        }
+
+        ui.add_sized([size[0] - star_size[0] - ui.spacing().item_spacing.x, size[1]], button)
+    }).inner;
+
+    response.context_menu(|ui| {
+        if ui.button("Edit sound").clicked() {
+            open_sound_editor(app_state, file_path);
+            ui.close();
+        }
+    });
+
+    response
+}
+
+fn open_sound_editor(app_state: &mut AppState, file_path: &str) {
+    let sound_id = sound_id(app_state, file_path);
+    let metadata = app_state.json_data.sound_metadata.get(&sound_id).cloned().unwrap_or_default();
+
+    app_state.edited_sound = Some(EditSoundState {
+        sound_id,
+        file_path: file_path.to_string(),
+        tags_text: metadata.tags.join(", "),
+        metadata,
+    });
+}
+
+pub fn edit_sound_ui(ctx: &Context, app_state: &mut AppState) {
+    let Some(edited_sound) = &mut app_state.edited_sound else {
+        return;
+    };
+
+    let mut save = false;
+    let mut close = false;
+
+    egui::Window::new("Edit sound")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(ctx, |ui| {
+            ui.label(filename(&edited_sound.file_path));
+            ui.separator();
+
+            egui::Grid::new("edit_sound_grid").num_columns(2).show(ui, |ui| {
+                ui.label("Display name");
+                ui.text_edit_singleline(&mut edited_sound.metadata.display_name);
+                ui.end_row();
+
+                ui.label("Tags (comma separated)");
+                ui.text_edit_singleline(&mut edited_sound.tags_text);
+                ui.end_row();
+
+                ui.label("Icon / Emoji");
+                ui.text_edit_singleline(&mut edited_sound.metadata.icon);
+                ui.end_row();
+
+                ui.label("Button color");
+                ui.horizontal(|ui| {
+                    let mut has_color = edited_sound.metadata.color.is_some();
+                    if ui.checkbox(&mut has_color, "Custom").changed() {
+                        edited_sound.metadata.color = if has_color { Some([80, 80, 80]) } else { None };
+                    }
+                    if let Some(color) = &mut edited_sound.metadata.color {
+                        ui.color_edit_button_srgb(color);
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Notes");
+                ui.text_edit_multiline(&mut edited_sound.metadata.notes);
+                ui.end_row();
+            });
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Save").clicked() {
+                    save = true;
+                }
+                if ui.button("Cancel").clicked() {
+                    close = true;
+                }
+            });
+        });
+
+    if save {
+        if let Some(mut edited_sound) = app_state.edited_sound.take() {
+            edited_sound.metadata.tags = edited_sound
+                .tags_text
+                .split(',')
+                .map(|tag| tag.trim().to_string())
+                .filter(|tag| !tag.is_empty())
+                .collect();
+
+            app_state.json_data.sound_metadata.insert(edited_sound.sound_id, edited_sound.metadata);
+            save_data(app_state);
+        }
+    }
+    else if close {
+        app_state.edited_sound = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_keyed_data_moves_to_the_hash() {
+        let mut app_state = crate::new_app_state(crate::SoundSystem::null());
+        app_state.json_data.sound_metadata.insert("/sounds/airhorn.mp3".to_string(), SoundMetadata { display_name: "Horn".to_string(), ..Default::default() });
+        app_state.json_data.favorites = vec!["/sounds/airhorn.mp3".to_string(), "abc".to_string()];
+        app_state.json_data.recent = vec!["/sounds/airhorn.mp3".to_string(), "def".to_string(), "abc".to_string()];
+        open_sound_editor(&mut app_state, "/sounds/airhorn.mp3");
+
+        assert!(adopt_sound_id(&mut app_state, "/sounds/airhorn.mp3", "abc"));
+        assert_eq!(sound_id(&app_state, "/sounds/airhorn.mp3"), "abc");
+        assert_eq!(app_state.json_data.sound_metadata.get("abc").map(|metadata| metadata.display_name.as_str()), Some("Horn"));
+        assert!(!app_state.json_data.sound_metadata.contains_key("/sounds/airhorn.mp3"));
+        assert_eq!(app_state.json_data.favorites, vec!["abc".to_string()]);
+        assert_eq!(app_state.json_data.recent, vec!["abc".to_string(), "def".to_string()]);
+        assert_eq!(app_state.edited_sound.as_ref().map(|edited_sound| edited_sound.sound_id.as_str()), Some("abc"));
+
+        assert!(!adopt_sound_id(&mut app_state, "/sounds/bruh.wav", "ghi")); // nothing was saved under that path
+    }
+}
@@ -0,0 +1,334 @@
+use std::{sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}, thread, time::{Duration, Instant}};
+
+use bevy::prelude::ResMut;
+use bevy_egui::egui::{self, Color32, Context};
+use rand::{Rng, distr::Alphanumeric};
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tungstenite::{Message, WebSocket, handshake::derive_accept_key, protocol::Role};
+
+use crate::{AppState, control::{ControlCommand, ControlRequest, ControlResponse, submit}, import::{notify, percent_decode}, save_data};
+
+const REMOTE_PAGE: &str = include_str!("remote.html");
+const STATUS_INTERVAL: Duration = Duration::from_millis(250);
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(5); // resend even when nothing changed, so closed sockets are noticed
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct RemoteSettings {
+    pub enabled: bool,
+    pub bind_address: String,
+    pub token: String,
+}
+
+impl Default for RemoteSettings {
+    fn default() -> Self {
+        RemoteSettings {
+            enabled: false,
+            bind_address: "0.0.0.0:8787".to_string(),
+            token: String::new(),
+        }
+    }
+}
+
+pub struct RemoteServer {
+    server: Arc<Server>,
+    shutdown: Arc<AtomicBool>, // open websockets check it, they would otherwise keep streaming after the server is turned off
+}
+
+impl Drop for RemoteServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        self.server.unblock(); // ends the accept loop
+    }
+}
+
+#[derive(Deserialize)]
+struct PlayBody {
+    sound: String,
+}
+
+pub fn generate_token() -> String {
+    rand::rng().sample_iter(&Alphanumeric).take(24).map(char::from).collect()
+}
+
+fn query_param(query: &str, key: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| percent_decode(&value.replace('+', " ")))
+}
+
+// compares every byte so the time taken doesn't give away how much of a guessed token was right
+fn tokens_match(given: &str, token: &str) -> bool {
+    given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+fn header<'a>(request: &'a Request, name: &str) -> Option<&'a str> {
+    request.headers().iter().find(|header| header.field.as_str().as_str().eq_ignore_ascii_case(name)).map(|header| header.value.as_str())
+}
+
+fn json_response(status: u16, body: &ControlResponse) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string(serde_json::to_string(body).expect("Could not convert response to JSON"))
+        .with_status_code(status)
+        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).expect("Invalid header"))
+}
+
+fn respond(request: Request, response: ControlResponse) {
+    let status = if response.ok { 200 } else { 400 };
+    let _ = request.respond(json_response(status, &response));
+}
+
+fn stream_now_playing(request: Request, requests: &Arc<Mutex<Vec<ControlRequest>>>, shutdown: &AtomicBool) {
+    let Some(key) = header(&request, "Sec-WebSocket-Key").map(str::to_string) else {
+        respond(request, ControlResponse::error("Expected a websocket upgrade.".to_string()));
+        return;
+    };
+
+    let accept = Header::from_bytes(&b"Sec-WebSocket-Accept"[..], derive_accept_key(key.as_bytes()).as_bytes()).expect("Invalid header");
+    let stream = request.upgrade("websocket", Response::empty(101).with_header(accept));
+    let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+
+    let mut last_status = String::new();
+    let mut last_sent = Instant::now();
+    while !shutdown.load(Ordering::Relaxed) {
+        let response = submit(requests, ControlCommand::Status);
+        if !response.ok {
+            break; // the app is gone or stuck
+        }
+
+        let status = serde_json::to_string(&response).expect("Could not convert response to JSON");
+        if status != last_status || last_sent.elapsed() >= KEEPALIVE_INTERVAL {
+            if socket.send(Message::text(status.clone())).is_err() {
+                break;
+            }
+            last_status = status;
+            last_sent = Instant::now();
+        }
+        thread::sleep(STATUS_INTERVAL);
+    }
+    let _ = socket.close(None);
+    let _ = socket.flush();
+}
+
+fn handle_request(mut request: Request, token: &str, requests: &Arc<Mutex<Vec<ControlRequest>>>, shutdown: &AtomicBool) {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+
+    if *request.method() == Method::Get && path == "/" {
+        let page = Header::from_bytes(&b"Content-Type"[..], &b"text/html; charset=utf-8"[..]).expect("Invalid header");
+        let _ = request.respond(Response::from_string(REMOTE_PAGE).with_header(page));
+        return;
+    }
+
+    // browsers can't set headers on websockets, so the token may also come as ?token=
+    let given_token = header(&request, "Authorization").and_then(|value| value.strip_prefix("Bearer ")).map(str::to_string).or_else(|| query_param(query, "token"));
+    if !given_token.is_some_and(|given_token| tokens_match(&given_token, token)) {
+        let _ = request.respond(json_response(401, &ControlResponse::error("Missing or wrong token.".to_string())));
+        return;
+    }
+
+    match (request.method(), path) {
+        (Method::Get, "/api/tabs") => respond(request, submit(requests, ControlCommand::Tabs)),
+        (Method::Get, "/api/sounds") => {
+            let tab = query_param(query, "tab");
+            respond(request, submit(requests, ControlCommand::List { tab }));
+        }
+        (Method::Get, "/api/now-playing") => respond(request, submit(requests, ControlCommand::Status)),
+        (Method::Get, "/api/ws") => stream_now_playing(request, requests, shutdown),
+        (Method::Post, "/api/play") => {
+            let mut body = String::new();
+            let _ = request.as_reader().read_to_string(&mut body);
+            match serde_json::from_str::<PlayBody>(&body) {
+                Ok(body) => respond(request, submit(requests, ControlCommand::Play { sound: body.sound })),
+                Err(err) => respond(request, ControlResponse::error(format!("Expected {{\"sound\": \"...\"}}: {}", err))),
+            }
+        }
+        (Method::Post, "/api/stop") => respond(request, submit(requests, ControlCommand::Stop)),
+        _ => {
+            let _ = request.respond(json_response(404, &ControlResponse::error(format!("Nothing at {}", path))));
+        }
+    }
+}
+
+pub fn start_remote_server(settings: &RemoteSettings, requests: &Arc<Mutex<Vec<ControlRequest>>>) -> Result<RemoteServer, String> {
+    if settings.token.is_empty() {
+        return Err("The remote control needs a token.".to_string());
+    }
+
+    let server = Arc::new(Server::http(&settings.bind_address).map_err(|err| format!("Could not listen on {}: {}", settings.bind_address, err))?);
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+
+    let accepting = Arc::clone(&server);
+    let token = settings.token.clone();
+    let requests = Arc::clone(requests);
+    let stopping = Arc::clone(&shutdown);
+    thread::spawn(move || {
+        for request in accepting.incoming_requests() {
+            let token = token.clone();
+            let requests = Arc::clone(&requests);
+            let stopping = Arc::clone(&stopping);
+            thread::spawn(move || handle_request(request, &token, &requests, &stopping));
+        }
+    });
+
+    Ok(RemoteServer { server, shutdown })
+}
+
+pub fn start_remote_if_enabled(app_state: &mut AppState) {
+    if !app_state.json_data.remote.enabled {
+        return;
+    }
+
+    match start_remote_server(&app_state.json_data.remote, &app_state.control_requests) {
+        Ok(server) => app_state.remote_server = Some(server),
+        Err(err) => notify(app_state, err),
+    }
+}
+
+pub fn remote_ui(ctx: &Context, mut app_state: ResMut<AppState>) {
+    egui::CentralPanel::default().show(ctx, |ui| {
+        ui.heading("Remote control");
+        ui.label("Lets a phone or another PC on your network play sounds through a web page. Anyone with the token can play sounds, so only share it with people you trust.");
+        ui.separator();
+
+        let running = app_state.remote_server.is_some();
+        let mut settings = app_state.json_data.remote.clone();
+
+        ui.add_enabled_ui(!running, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Listen on");
+                ui.text_edit_singleline(&mut settings.bind_address);
+            });
+            ui.horizontal(|ui| {
+                ui.label("Token");
+                ui.text_edit_singleline(&mut settings.token);
+                if ui.button("New token").clicked() {
+                    settings.token = generate_token();
+                }
+            });
+        });
+
+        let mut enabled = running;
+        if ui.checkbox(&mut enabled, "Enable the remote control server").changed() {
+            if enabled {
+                if settings.token.is_empty() {
+                    settings.token = generate_token();
+                }
+                match start_remote_server(&settings, &app_state.control_requests) {
+                    Ok(server) => app_state.remote_server = Some(server),
+                    Err(err) => notify(&mut app_state, err),
+                }
+            }
+            else {
+                app_state.remote_server = None;
+            }
+            settings.enabled = app_state.remote_server.is_some();
+        }
+
+        if settings != app_state.json_data.remote {
+            app_state.json_data.remote = settings;
+            save_data(&app_state);
+        }
+
+        if app_state.remote_server.is_some() {
+            let port = app_state.json_data.remote.bind_address.rsplit(':').next().unwrap_or_default().to_string();
+            ui.colored_label(Color32::GREEN, format!("Running, open http://<this computer's address>:{}/?token={} on your phone.", port, app_state.json_data.remote.token));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::answer_requests;
+
+    fn start_test_server() -> (RemoteServer, String) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let settings = RemoteSettings { enabled: true, bind_address: "127.0.0.1:0".to_string(), token: "secret".to_string() };
+        let server = start_remote_server(&settings, &requests).unwrap();
+        answer_requests(requests);
+
+        let address = server.server.server_addr().to_ip().unwrap().to_string();
+        (server, address)
+    }
+
+    #[test]
+    fn reads_query_parameters() {
+        assert_eq!(query_param("tab=%2Fhome%2Fsounds&token=abc", "tab").as_deref(), Some("/home/sounds"));
+        assert_eq!(query_param("tab=my+sounds", "tab").as_deref(), Some("my sounds"));
+        assert_eq!(query_param("token=abc", "tab"), None);
+    }
+
+    #[test]
+    fn needs_the_token_for_the_api() {
+        let (_server, address) = start_test_server();
+        let client = reqwest::blocking::Client::new();
+
+        let page = client.get(format!("http://{}/", address)).send().unwrap();
+        assert_eq!(page.status(), 200);
+        assert!(page.text().unwrap().contains("<html"));
+
+        assert_eq!(client.get(format!("http://{}/api/sounds", address)).send().unwrap().status(), 401);
+        assert_eq!(client.get(format!("http://{}/api/sounds?token=wrong", address)).send().unwrap().status(), 401);
+        assert_eq!(client.get(format!("http://{}/api/sounds", address)).bearer_auth("secret").send().unwrap().status(), 200);
+    }
+
+    #[test]
+    fn lists_and_plays_sounds() {
+        let (_server, address) = start_test_server();
+        let client = reqwest::blocking::Client::new();
+
+        let listed: ControlResponse = serde_json::from_str(&client.get(format!("http://{}/api/sounds?tab=%2Fsounds&token=secret", address)).send().unwrap().text().unwrap()).unwrap();
+        assert_eq!(listed.sounds.unwrap()[0].tab, "/sounds");
+
+        let played = client.post(format!("http://{}/api/play", address)).bearer_auth("secret").body(r#"{"sound":"airhorn"}"#).send().unwrap();
+        assert_eq!(played.status(), 200);
+
+        let missing = client.post(format!("http://{}/api/play", address)).bearer_auth("secret").body(r#"{"sound":"missing"}"#).send().unwrap();
+        assert_eq!(missing.status(), 400);
+
+        assert_eq!(client.post(format!("http://{}/api/stop", address)).bearer_auth("secret").send().unwrap().status(), 200);
+        assert_eq!(client.get(format!("http://{}/api/nothing", address)).bearer_auth("secret").send().unwrap().status(), 404);
+    }
+
+    #[test]
+    fn streams_now_playing_over_websockets() {
+        let (_server, address) = start_test_server();
+
+        assert!(tungstenite::connect(format!("ws://{}/api/ws", address)).is_err());
+
+        let (mut socket, _) = tungstenite::connect(format!("ws://{}/api/ws?token=secret", address)).unwrap();
+        let status: ControlResponse = serde_json::from_str(socket.read().unwrap().to_text().unwrap()).unwrap();
+        assert_eq!(status.playing, Some(Vec::new()));
+    }
+
+    #[test]
+    fn turning_the_server_off_closes_websockets() {
+        let (server, address) = start_test_server();
+        let (mut socket, _) = tungstenite::connect(format!("ws://{}/api/ws?token=secret", address)).unwrap();
+        if let tungstenite::stream::MaybeTlsStream::Plain(stream) = socket.get_ref() {
+            stream.set_read_timeout(Some(Duration::from_secs(3))).unwrap(); // the app keeps answering, so only the flag can end the stream
+        }
+        socket.read().unwrap();
+
+        drop(server);
+        loop {
+            match socket.read() {
+                Ok(Message::Close(_)) | Err(tungstenite::Error::ConnectionClosed) => break,
+                Ok(_) => continue,
+                Err(err) => panic!("the websocket stayed open: {}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn compares_tokens() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secreT", "secret"));
+        assert!(!tokens_match("secret2", "secret"));
+        assert!(!tokens_match("", "secret"));
+    }
+}
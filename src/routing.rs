@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+
+use bevy::prelude::ResMut;
+use bevy_egui::egui::{self, Color32, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{AppState, latency::latency_settings_ui, save_data};
+
+const DEFAULT_APPS_TO_EXCLUDE: [&str; 7] = ["plasmashell", "pavucontrol", "pipewire", "wireplumber", "kwin_wayland", "kwin_x11", "obs"];
+const DEFAULT_NODE_NAMES_TO_EXCLUDE: [&str; 2] = ["VirtualMicSource", "SoundboardSink"];
+
+#[derive(Clone, PartialEq)]
+pub struct AppOutput {
+    pub index: String, // pactl source-output index, changes every time the app reconnects
+    pub app_name: String,
+    pub binary: String,
+    pub node_name: String,
+    pub source: String, // index of the source the app currently records from
+    pub object_id: String, // pipewire node id, used for links
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum RoutingMode {
+    #[default]
+    Pactl, // apps record from VirtualMicSource, which mixes the mic and the soundboard
+    PipewireLinks, // the soundboard is linked straight into the app's input ports next to the real mic
+}
+
+impl RoutingMode {
+    pub fn label(self) -> &'static str {
+        match self {
+            RoutingMode::Pactl => "Virtual mic (PulseAudio modules)",
+            RoutingMode::PipewireLinks => "Direct PipeWire links",
+        }
+    }
+}
+
+pub struct AppliedRouting {
+    pub routed: bool,
+    pub original_source: String, // where the stream was before we moved it into the virtual mic
+}
+
+#[derive(PartialEq, Debug)]
+pub struct RoutingMove {
+    pub output_index: String,
+    pub source: String,
+}
+
+impl AppOutput {
+    pub fn label(&self) -> String {
+        format!("{} ({})", self.app_name, self.binary)
+    }
+
+    fn rule_key(&self) -> &str { // what a new rule for this app matches on, the binary survives renamed windows
+        if self.binary.is_empty() || self.binary == "Unknown" { &self.app_name } else { &self.binary }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct RoutingRule {
+    pub pattern: String, // matched against application.process.binary and application.name, * and ? are wildcards
+    pub route: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct ExclusionList {
+    pub apps: Vec<String>, // matched like routing rules
+    pub node_names: Vec<String>, // keeps our own virtual devices out of the list
+}
+
+impl Default for ExclusionList {
+    fn default() -> Self {
+        ExclusionList {
+            apps: DEFAULT_APPS_TO_EXCLUDE.iter().map(|app| app.to_string()).collect(),
+            node_names: DEFAULT_NODE_NAMES_TO_EXCLUDE.iter().map(|node_name| node_name.to_string()).collect(),
+        }
+    }
+}
+
+pub struct RoutingUiState {
+    pub new_pattern: String,
+    pub new_excluded_app: String,
+}
+
+// case insensitive glob matching, * matches any run of characters and ? a single one
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let (mut pattern_index, mut text_index) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None; // position after the last *, and the text position it currently covers up to
+
+    while text_index < text.len() {
+        match pattern.get(pattern_index) {
+            Some('*') => {
+                backtrack = Some((pattern_index + 1, text_index));
+                pattern_index += 1;
+            }
+            Some(&char) if char == '?' || char == text[text_index] => {
+                pattern_index += 1;
+                text_index += 1;
+            }
+            _ => {
+                let Some((star_pattern_index, star_text_index)) = backtrack else {
+                    return false;
+                };
+                backtrack = Some((star_pattern_index, star_text_index + 1));
+                pattern_index = star_pattern_index;
+                text_index = star_text_index + 1;
+            }
+        }
+    }
+
+    pattern[pattern_index..].iter().all(|char| *char == '*')
+}
+
+pub fn is_excluded(exclusions: &ExclusionList, output: &AppOutput) -> bool {
+    exclusions.apps.iter().any(|pattern| wildcard_match(pattern, &output.binary) || wildcard_match(pattern, &output.app_name))
+        || exclusions.node_names.iter().any(|pattern| wildcard_match(pattern, &output.node_name))
+}
+
+pub fn hide_app(app_state: &mut AppState, output: &AppOutput) {
+    app_state.json_data.exclusions.apps.push(output.rule_key().to_string());
+    app_state.virt_outputs.retain(|virt_output| virt_output.index != output.index);
+    save_data(app_state);
+}
+
+pub fn matching_rule<'a>(rules: &'a [RoutingRule], output: &AppOutput) -> Option<&'a RoutingRule> { // the first matching rule wins
+    rules.iter().find(|rule| wildcard_match(&rule.pattern, &output.binary) || wildcard_match(&rule.pattern, &output.app_name))
+}
+
+pub fn is_routed(rules: &[RoutingRule], output: &AppOutput) -> bool {
+    matching_rule(rules, output).is_some_and(|rule| rule.route)
+}
+
+// flips the app by adding or updating an exact rule in front, so broader wildcard rules keep working for other apps
+pub fn toggle_routing(rules: &mut Vec<RoutingRule>, output: &AppOutput) {
+    let route = !is_routed(rules, output);
+    let key = output.rule_key().to_string();
+
+    match rules.iter().position(|rule| rule.pattern == key) {
+        Some(position) => {
+            let mut rule = rules.remove(position);
+            rule.route = route;
+            rules.insert(0, rule);
+        }
+        None => rules.insert(0, RoutingRule { pattern: key, route }),
+    }
+}
+
+// only returns moves for streams whose routing changed since the last call, so moves done in pavucontrol are left alone
+pub fn plan_routing(applied: &mut HashMap<String, AppliedRouting>, outputs: &[AppOutput], rules: &[RoutingRule], virtual_mic_source: &str, default_source: &str) -> Vec<RoutingMove> {
+    applied.retain(|index, _| outputs.iter().any(|output| output.index == *index)); // indexes of closed streams get reused
+    let mut moves = Vec::new();
+
+    for output in outputs {
+        let routed = is_routed(rules, output);
+        let previous = applied.get(&output.index).map(|applied_routing| applied_routing.routed);
+
+        if routed && previous != Some(true) {
+            if output.source != virtual_mic_source {
+                moves.push(RoutingMove { output_index: output.index.clone(), source: virtual_mic_source.to_string() });
+            }
+            applied.insert(output.index.clone(), AppliedRouting { routed, original_source: output.source.clone() });
+        }
+        else if !routed && previous == Some(true) {
+            let original_source = applied[&output.index].original_source.clone();
+            if output.source == virtual_mic_source { // if it is somewhere else, the user moved it and it stays there
+                let source = if original_source == virtual_mic_source { default_source.to_string() } else { original_source.clone() };
+                moves.push(RoutingMove { output_index: output.index.clone(), source });
+            }
+            applied.insert(output.index.clone(), AppliedRouting { routed, original_source });
+        }
+        else if previous.is_none() {
+            applied.insert(output.index.clone(), AppliedRouting { routed, original_source: output.source.clone() });
+        }
+    }
+
+    moves
+}
+
+// link mode equivalent of plan_routing, returns the streams to link (true) or unlink (false)
+pub fn plan_links(applied: &mut HashMap<String, AppliedRouting>, outputs: &[AppOutput], rules: &[RoutingRule]) -> Vec<(AppOutput, bool)> {
+    applied.retain(|index, _| outputs.iter().any(|output| output.index == *index));
+    let mut changes = Vec::new();
+
+    for output in outputs {
+        let routed = is_routed(rules, output);
+        let previous = applied.get(&output.index).map(|applied_routing| applied_routing.routed).unwrap_or(false);
+        if routed != previous {
+            changes.push((output.clone(), routed));
+        }
+        applied.insert(output.index.clone(), AppliedRouting { routed, original_source: output.source.clone() });
+    }
+
+    changes
+}
+
+pub fn routing_ui(ctx: &Context, mut app_state: ResMut<AppState>) {
+    egui::CentralPanel::default().show(ctx, |ui| {
+        ui.heading("Routing mode");
+        let mut routing_mode = app_state.json_data.routing_mode;
+        egui::ComboBox::from_id_salt("Routing Mode Selector")
+            .selected_text(routing_mode.label())
+            .show_ui(ui, |ui| {
+                for mode in [RoutingMode::Pactl, RoutingMode::PipewireLinks] {
+                    ui.selectable_value(&mut routing_mode, mode, mode.label());
+                }
+            });
+        if routing_mode != app_state.json_data.routing_mode {
+            app_state.json_data.routing_mode = routing_mode;
+            save_data(&app_state);
+        }
+        #[cfg(target_os = "linux")]
+        if app_state.json_data.routing_mode != app_state.sound_system.routing_mode {
+            ui.colored_label(Color32::YELLOW, format!("Currently using {}, press Reload sound system or restart to switch.", app_state.sound_system.routing_mode.label()));
+        }
+        ui.separator();
+
+        ui.heading("Routing rules");
+        ui.label("Apps matching a rule are routed into the virtual mic automatically whenever they show up. The first matching rule wins, * and ? work as wildcards.");
+        ui.separator();
+
+        let mut rules = app_state.json_data.routing_rules.clone();
+        let mut removed = None;
+
+        for (index, rule) in rules.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut rule.pattern);
+                ui.checkbox(&mut rule.route, "Route into virtual mic");
+
+                let matches: Vec<String> = app_state
+                    .virt_outputs
+                    .iter()
+                    .filter(|output| wildcard_match(&rule.pattern, &output.binary) || wildcard_match(&rule.pattern, &output.app_name))
+                    .map(AppOutput::label)
+                    .collect();
+                if !matches.is_empty() {
+                    ui.colored_label(Color32::LIGHT_BLUE, matches.join(", "));
+                }
+
+                if ui.button("Remove").clicked() {
+                    removed = Some(index);
+                }
+            });
+        }
+        if let Some(index) = removed {
+            rules.remove(index);
+        }
+
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut app_state.routing_ui_state.new_pattern);
+            if ui.button("Add rule").clicked() && !app_state.routing_ui_state.new_pattern.trim().is_empty() {
+                rules.push(RoutingRule { pattern: app_state.routing_ui_state.new_pattern.trim().to_string(), route: true });
+                app_state.routing_ui_state.new_pattern.clear();
+            }
+        });
+
+        if rules != app_state.json_data.routing_rules {
+            app_state.json_data.routing_rules = rules;
+            save_data(&app_state);
+        }
+
+        ui.separator();
+        ui.heading("Hidden apps");
+        ui.label("Apps matching these patterns never show up in the virtual mic list and are never moved.");
+
+        let mut exclusions = app_state.json_data.exclusions.clone();
+        let mut removed = None;
+        for (index, pattern) in exclusions.apps.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(pattern);
+                if ui.button("Show again").clicked() {
+                    removed = Some(index);
+                }
+            });
+        }
+        if let Some(index) = removed {
+            exclusions.apps.remove(index);
+        }
+
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut app_state.routing_ui_state.new_excluded_app);
+            if ui.button("Hide app").clicked() && !app_state.routing_ui_state.new_excluded_app.trim().is_empty() {
+                exclusions.apps.push(app_state.routing_ui_state.new_excluded_app.trim().to_string());
+                app_state.routing_ui_state.new_excluded_app.clear();
+            }
+        });
+
+        if ui.button("Reset to defaults").clicked() {
+            exclusions = ExclusionList::default();
+        }
+
+        if exclusions != app_state.json_data.exclusions {
+            app_state.json_data.exclusions = exclusions;
+            save_data(&app_state);
+        }
+
+        ui.separator();
+        latency_settings_ui(ui, &mut app_state);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(app_name: &str, binary: &str) -> AppOutput {
+        AppOutput { index: "1".to_string(), app_name: app_name.to_string(), binary: binary.to_string(), node_name: format!("{}-input", binary), source: "10".to_string(), object_id: "50".to_string() }
+    }
+
+    fn rule(pattern: &str, route: bool) -> RoutingRule {
+        RoutingRule { pattern: pattern.to_string(), route }
+    }
+
+    fn move_to(output_index: &str, source: &str) -> RoutingMove {
+        RoutingMove { output_index: output_index.to_string(), source: source.to_string() }
+    }
+
+    #[test]
+    fn moves_only_when_routing_changes() {
+        let mut applied = HashMap::new();
+        let mut discord = output("Discord", "Discord");
+        let mut rules = vec![rule("discord", true)];
+
+        assert_eq!(plan_routing(&mut applied, &[discord.clone()], &rules, "99", "1"), vec![move_to("1", "99")]);
+        discord.source = "99".to_string();
+        assert_eq!(plan_routing(&mut applied, &[discord.clone()], &rules, "99", "1"), vec![]);
+
+        // deselecting restores the source it had before, not the default one
+        rules[0].route = false;
+        assert_eq!(plan_routing(&mut applied, &[discord.clone()], &rules, "99", "1"), vec![move_to("1", "10")]);
+        discord.source = "10".to_string();
+        assert_eq!(plan_routing(&mut applied, &[discord.clone()], &rules, "99", "1"), vec![]);
+    }
+
+    #[test]
+    fn leaves_streams_moved_by_the_user_alone() {
+        let mut applied = HashMap::new();
+        let mut firefox = output("Firefox", "firefox");
+
+        assert_eq!(plan_routing(&mut applied, &[firefox.clone()], &[], "99", "1"), vec![]);
+        firefox.source = "42".to_string(); // moved in pavucontrol
+        assert_eq!(plan_routing(&mut applied, &[firefox.clone()], &[], "99", "1"), vec![]);
+
+        let rules = vec![rule("firefox", true)];
+        assert_eq!(plan_routing(&mut applied, &[firefox.clone()], &rules, "99", "1"), vec![move_to("1", "99")]);
+
+        firefox.source = "7".to_string(); // moved away from the virtual mic while routed
+        assert_eq!(plan_routing(&mut applied, &[firefox.clone()], &[], "99", "1"), vec![]);
+    }
+
+    #[test]
+    fn links_only_on_changes() {
+        let mut applied = HashMap::new();
+        let discord = output("Discord", "Discord");
+        let rules = vec![rule("discord", true)];
+
+        assert_eq!(plan_links(&mut applied, std::slice::from_ref(&discord), &[]).len(), 0);
+        assert!(plan_links(&mut applied, std::slice::from_ref(&discord), &rules) == vec![(discord.clone(), true)]);
+        assert_eq!(plan_links(&mut applied, std::slice::from_ref(&discord), &rules).len(), 0);
+        assert!(plan_links(&mut applied, std::slice::from_ref(&discord), &[]) == vec![(discord.clone(), false)]);
+    }
+
+    #[test]
+    fn falls_back_to_the_default_source() {
+        let mut applied = HashMap::new();
+        let mut game = output("Game", "game");
+        game.source = "99".to_string(); // already recording from the virtual mic when it appeared
+
+        assert_eq!(plan_routing(&mut applied, &[game.clone()], &[rule("game", true)], "99", "1"), vec![]);
+        assert_eq!(plan_routing(&mut applied, &[game.clone()], &[], "99", "1"), vec![move_to("1", "1")]);
+
+        assert_eq!(plan_routing(&mut applied, &[], &[], "99", "1"), vec![]);
+        assert!(applied.is_empty());
+    }
+
+    #[test]
+    fn excludes_default_and_custom_apps() {
+        let mut exclusions = ExclusionList::default();
+        assert!(is_excluded(&exclusions, &output("OBS", "obs")));
+        assert!(!is_excluded(&exclusions, &output("GNOME Shell", "gnome-shell")));
+
+        let mut own_device = output("Soundboard", "soundboard");
+        own_device.node_name = "VirtualMicSource".to_string();
+        assert!(is_excluded(&exclusions, &own_device));
+
+        exclusions.apps.push("xdg-desktop-portal*".to_string());
+        exclusions.apps.push("GNOME Shell".to_string());
+        assert!(is_excluded(&exclusions, &output("Portal", "xdg-desktop-portal-gnome")));
+        assert!(is_excluded(&exclusions, &output("GNOME Shell", "gnome-shell")));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(wildcard_match("discord", "Discord"));
+        assert!(wildcard_match("discord*", "DiscordCanary"));
+        assert!(wildcard_match("*cord*", "discord"));
+        assert!(wildcard_match("fire?ox", "firefox"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("a*b*c", "axxbyybc"));
+        assert!(!wildcard_match("discord", "discord-ptb"));
+        assert!(!wildcard_match("a*b", "acbd"));
+        assert!(!wildcard_match("?", ""));
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = vec![
+            RoutingRule { pattern: "DiscordCanary".to_string(), route: false },
+            RoutingRule { pattern: "discord*".to_string(), route: true },
+        ];
+
+        assert!(is_routed(&rules, &output("Discord", "Discord")));
+        assert!(!is_routed(&rules, &output("Discord Canary", "DiscordCanary")));
+        assert!(is_routed(&rules, &output("discord", "Unknown")));
+        assert!(!is_routed(&rules, &output("Firefox", "firefox")));
+    }
+
+    #[test]
+    fn toggling_adds_an_exact_rule_in_front() {
+        let mut rules = vec![RoutingRule { pattern: "*".to_string(), route: true }];
+        let firefox = output("Firefox", "firefox");
+
+        toggle_routing(&mut rules, &firefox);
+        assert_eq!(rules[0], RoutingRule { pattern: "firefox".to_string(), route: false });
+        assert!(is_routed(&rules, &output("Discord", "Discord")));
+
+        toggle_routing(&mut rules, &firefox);
+        assert_eq!(rules.len(), 2);
+        assert!(is_routed(&rules, &firefox));
+
+        toggle_routing(&mut rules, &output("Some Game", "Unknown"));
+        assert_eq!(rules[0].pattern, "Some Game");
+    }
+}
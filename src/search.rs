@@ -0,0 +1,65 @@
+use crate::{AppState, metadata::search_candidates, virtual_tabs::tab_files};
+
+pub struct SearchState {
+    pub query: String,
+    pub search_all_tabs: bool,
+    pub selected_index: usize,
+}
+
+// every query character has to appear in order, consecutive matches and matches at the start of a word score higher
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut position = 0;
+    let mut last_match: Option<usize> = None;
+
+    for query_char in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let index = (position..candidate.len()).find(|&i| candidate[i] == query_char)?;
+
+        score += 1;
+        if last_match.is_some_and(|last| last + 1 == index) {
+            score += 5;
+        }
+        if index == 0 || !candidate[index - 1].is_alphanumeric() {
+            score += 3;
+        }
+        score -= (index - position) as i64;
+
+        last_match = Some(index);
+        position = index + 1;
+    }
+
+    Some(score)
+}
+
+pub fn search_sounds(app_state: &AppState) -> Vec<(String, String)> { // returns (tab, file path) pairs, best match first
+    let query = app_state.search_state.query.trim();
+
+    let sounds: Vec<(String, String)> = if app_state.search_state.search_all_tabs {
+        app_state
+            .loaded_files
+            .iter()
+            .flat_map(|(tab, files)| files.iter().map(move |file| (tab.clone(), file.clone())))
+            .collect()
+    }
+    else {
+        tab_files(app_state, &app_state.current_directory)
+            .into_iter()
+            .map(|file| (app_state.current_directory.clone(), file))
+            .collect()
+    };
+
+    let mut results: Vec<(i64, String, String)> = sounds
+        .into_iter()
+        .filter_map(|(tab, file)| {
+            let score = search_candidates(app_state, &file)
+                .iter()
+                .filter_map(|candidate| fuzzy_score(query, candidate))
+                .max()?;
+            Some((score, tab, file))
+        })
+        .collect();
+
+    results.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.2.cmp(&b.2)));
+    results.into_iter().map(|(_, tab, file)| (tab, file)).collect()
+}
@@ -0,0 +1,165 @@
+use std::{sync::{Arc, Mutex}, thread};
+
+use bevy::prelude::ResMut;
+use bevy_egui::egui::{self, Color32, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{AppState, save_data, yt_dlp::{FFMPEG_INSTALL_HINT, YtDlpStatus, check_ffmpeg, install_ffmpeg, start_yt_dlp_update}};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct SetupSettings {
+    pub completed: bool,
+    pub youtube_enabled: bool,
+}
+
+impl Default for SetupSettings {
+    fn default() -> Self {
+        SetupSettings {
+            completed: false,
+            youtube_enabled: true,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum FfmpegStatus {
+    Unknown,
+    Checking,
+    Found,
+    Missing,
+    Installing,
+    InstallFailed(String),
+}
+
+pub fn start_ffmpeg_check(status: &Arc<Mutex<FfmpegStatus>>) {
+    *status.lock().expect("FFmpeg status lock poisoned") = FfmpegStatus::Checking;
+
+    let status = Arc::clone(status);
+    thread::spawn(move || {
+        let result = if check_ffmpeg() { FfmpegStatus::Found } else { FfmpegStatus::Missing };
+        *status.lock().expect("FFmpeg status lock poisoned") = result;
+    });
+}
+
+fn start_ffmpeg_install(status: &Arc<Mutex<FfmpegStatus>>) {
+    *status.lock().expect("FFmpeg status lock poisoned") = FfmpegStatus::Installing;
+
+    let status = Arc::clone(status);
+    thread::spawn(move || {
+        let result = match install_ffmpeg() {
+            Ok(()) if check_ffmpeg() => FfmpegStatus::Found,
+            Ok(()) => FfmpegStatus::InstallFailed("FFmpeg was installed, restart the soundboard to use it.".to_string()),
+            Err(err) => FfmpegStatus::InstallFailed(err),
+        };
+        *status.lock().expect("FFmpeg status lock poisoned") = result;
+    });
+}
+
+pub fn open_setup(app_state: &mut AppState) {
+    app_state.current_view = "setup".to_string();
+    start_ffmpeg_check(&app_state.ffmpeg_status);
+
+    let yt_dlp_unchecked = *app_state.yt_dlp_status.lock().expect("yt-dlp status lock poisoned") == YtDlpStatus::Unknown;
+    if app_state.json_data.setup.youtube_enabled && yt_dlp_unchecked {
+        start_yt_dlp_update(&app_state.json_data.yt_dlp, &app_state.yt_dlp_status);
+    }
+}
+
+fn finish_setup(app_state: &mut AppState, youtube_enabled: bool) {
+    app_state.json_data.setup = SetupSettings { completed: true, youtube_enabled };
+    save_data(app_state);
+    app_state.current_view = "main".to_string();
+}
+
+pub fn setup_ui(ctx: &Context, mut app_state: ResMut<AppState>) {
+    egui::CentralPanel::default().show(ctx, |ui| {
+        let available_width = ui.available_width();
+        let available_height = ui.available_height();
+
+        ui.heading("Setup");
+        ui.label("The soundboard itself works offline. FFmpeg and yt-dlp are only needed for some features, and you can come back here with the Setup button at any time.");
+        ui.separator();
+
+        ui.heading("FFmpeg");
+        ui.label("Used to convert opus, webm and similar files and by the YouTube downloader.");
+        let ffmpeg_status = app_state.ffmpeg_status.lock().expect("FFmpeg status lock poisoned").clone();
+        ui.horizontal(|ui| {
+            match &ffmpeg_status {
+                FfmpegStatus::Unknown | FfmpegStatus::Checking => {
+                    ui.spinner();
+                    ui.label("Checking...");
+                }
+                FfmpegStatus::Installing => {
+                    ui.spinner();
+                    ui.label("Installing with winget...");
+                }
+                FfmpegStatus::Found => {
+                    ui.colored_label(Color32::GREEN, "Found");
+                }
+                FfmpegStatus::Missing => {
+                    ui.colored_label(Color32::YELLOW, "Not found");
+                }
+                FfmpegStatus::InstallFailed(err) => {
+                    ui.colored_label(Color32::RED, err);
+                }
+            }
+
+            let busy = matches!(ffmpeg_status, FfmpegStatus::Unknown | FfmpegStatus::Checking | FfmpegStatus::Installing);
+            if ui.add_enabled(!busy, egui::Button::new("Check again")).clicked() {
+                start_ffmpeg_check(&app_state.ffmpeg_status);
+            }
+            if cfg!(target_os = "windows") && matches!(ffmpeg_status, FfmpegStatus::Missing | FfmpegStatus::InstallFailed(_)) && ui.button("Install with winget").clicked() {
+                start_ffmpeg_install(&app_state.ffmpeg_status);
+            }
+        });
+        if ffmpeg_status == FfmpegStatus::Missing && !cfg!(target_os = "windows") {
+            ui.label(FFMPEG_INSTALL_HINT);
+        }
+        ui.separator();
+
+        ui.heading("YouTube downloader");
+        let mut youtube_enabled = app_state.json_data.setup.youtube_enabled;
+        if ui.checkbox(&mut youtube_enabled, "Enable the YouTube downloader").changed() {
+            app_state.json_data.setup.youtube_enabled = youtube_enabled;
+            let yt_dlp_unchecked = *app_state.yt_dlp_status.lock().expect("yt-dlp status lock poisoned") == YtDlpStatus::Unknown;
+            if youtube_enabled && yt_dlp_unchecked {
+                start_yt_dlp_update(&app_state.json_data.yt_dlp, &app_state.yt_dlp_status);
+            }
+        }
+
+        if youtube_enabled {
+            let yt_dlp_status = app_state.yt_dlp_status.lock().expect("yt-dlp status lock poisoned").clone();
+            ui.horizontal(|ui| {
+                match &yt_dlp_status {
+                    YtDlpStatus::Unknown => {
+                        ui.label("yt-dlp not checked yet");
+                    }
+                    YtDlpStatus::Checking => {
+                        ui.spinner();
+                        ui.label("Downloading yt-dlp...");
+                    }
+                    YtDlpStatus::Ready(version) | YtDlpStatus::Updated(version) => {
+                        ui.colored_label(Color32::GREEN, format!("yt-dlp {} is ready", version));
+                    }
+                    YtDlpStatus::Failed(err) => {
+                        ui.colored_label(Color32::RED, err);
+                    }
+                }
+
+                if ui.add_enabled(yt_dlp_status != YtDlpStatus::Checking, egui::Button::new("Retry")).clicked() {
+                    start_yt_dlp_update(&app_state.json_data.yt_dlp, &app_state.yt_dlp_status);
+                }
+            });
+            ui.label("Downloads that fail now can be retried later, nothing here blocks the soundboard.");
+        }
+        ui.separator();
+
+        if ui.add_sized([available_width, available_height / 15.0], egui::Button::new("Continue")).clicked() {
+            finish_setup(&mut app_state, youtube_enabled);
+        }
+        if ui.add_sized([available_width, available_height / 15.0], egui::Button::new("Skip and stay offline")).clicked() {
+            finish_setup(&mut app_state, false);
+        }
+    });
+}
@@ -0,0 +1,160 @@
+use std::{collections::HashMap, fs::OpenOptions, io::Write, time::{SystemTime, UNIX_EPOCH}};
+
+use bevy::prelude::ResMut;
+use bevy_egui::egui::{self, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{AppState, PlayingSound, save_data, metadata::{sound_display_name, sound_id}};
+
+const STATS_FILE: &str = "stats.jsonl";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayTrigger {
+    Click,
+    Hotkey,
+    Cli,
+    Remote,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PlayRecord {
+    pub timestamp: u64,
+    pub sound_id: String,
+    pub file_path: String,
+    pub played_seconds: f32,
+    pub trigger: PlayTrigger,
+}
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0)
+}
+
+pub fn load_play_history() -> Vec<PlayRecord> {
+    let Ok(content) = std::fs::read_to_string(STATS_FILE) else {
+        return Vec::new();
+    };
+
+    content.lines().filter_map(|line| serde_json::from_str(line).ok()).collect() // skip lines broken by a crash mid-write
+}
+
+pub fn record_play(app_state: &mut AppState, playing_sound: &PlayingSound) {
+    let record = PlayRecord {
+        timestamp: playing_sound.started_at,
+        sound_id: sound_id(app_state, &playing_sound.file_path),
+        file_path: playing_sound.file_path.clone(),
+        played_seconds: playing_sound.sink.get_pos().as_secs_f32(),
+        trigger: playing_sound.trigger,
+    };
+
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(STATS_FILE) {
+        let _ = writeln!(file, "{}", serde_json::to_string(&record).expect("Could not convert play record to JSON"));
+    }
+
+    app_state.play_history.push(record);
+}
+
+pub fn play_counts(app_state: &AppState) -> HashMap<String, u32> {
+    let mut counts = HashMap::new();
+    for record in &app_state.play_history {
+        *counts.entry(record.sound_id.clone()).or_insert(0) += 1;
+    }
+    counts
+}
+
+pub fn play_count(counts: &HashMap<String, u32>, app_state: &AppState, file_path: &str) -> u32 {
+    counts.get(&sound_id(app_state, file_path)).copied().unwrap_or(0)
+}
+
+// the calendar day in the user's time zone, so an evening session isn't counted for the next day
+fn local_day(timestamp: u64) -> Option<(i64, i64, i64)> {
+    let time = timestamp as libc::time_t;
+    let mut local: libc::tm = unsafe { std::mem::zeroed() };
+
+    // SAFETY: both pointers point to live values of the types the C library expects
+    #[cfg(unix)]
+    let converted = !unsafe { libc::localtime_r(&time, &mut local) }.is_null();
+    #[cfg(windows)]
+    let converted = unsafe { libc::localtime_s(&mut local, &time) } == 0;
+
+    converted.then(|| (local.tm_year as i64 + 1900, local.tm_mon as i64 + 1, local.tm_mday as i64))
+}
+
+fn format_day(timestamp: u64) -> String {
+    let (year, month, day) = local_day(timestamp).unwrap_or_else(|| utc_day(timestamp));
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn utc_day(timestamp: u64) -> (i64, i64, i64) { // days since epoch to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+pub fn stats_ui(ctx: &Context, mut app_state: ResMut<AppState>) {
+    let counts = play_counts(&app_state);
+
+    let mut all_sounds: Vec<String> = app_state.loaded_files.values().flatten().cloned().collect();
+    all_sounds.sort();
+    all_sounds.dedup();
+
+    let mut most_played: Vec<(String, u32)> = all_sounds
+        .iter()
+        .map(|file_path| (file_path.clone(), play_count(&counts, &app_state, file_path)))
+        .filter(|(_, count)| *count > 0)
+        .collect();
+    most_played.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+    let never_played: Vec<&String> = all_sounds.iter().filter(|file_path| play_count(&counts, &app_state, file_path) == 0).collect();
+
+    let mut per_day: Vec<(String, u32)> = Vec::new();
+    for record in &app_state.play_history {
+        let day = format_day(record.timestamp);
+        match per_day.iter_mut().find(|(existing_day, _)| *existing_day == day) {
+            Some((_, count)) => *count += 1,
+            None => per_day.push((day, 1)),
+        }
+    }
+    per_day.sort_by(|a, b| b.0.cmp(&a.0));
+
+    egui::CentralPanel::default().show(ctx, |ui| {
+        let mut sort_by_play_count = app_state.json_data.sort_by_play_count;
+        if ui.checkbox(&mut sort_by_play_count, "Sort sound grid by play count").changed() {
+            app_state.json_data.sort_by_play_count = sort_by_play_count;
+            save_data(&app_state);
+        }
+        ui.label(format!("{} plays recorded", app_state.play_history.len()));
+        ui.separator();
+
+        ui.columns(3, |columns| {
+            columns[0].heading("Most played");
+            egui::ScrollArea::vertical().id_salt("most_played").show(&mut columns[0], |ui| {
+                for (file_path, count) in &most_played {
+                    ui.label(format!("{} - {}", sound_display_name(&app_state, file_path), count));
+                }
+            });
+
+            columns[1].heading("Never played");
+            egui::ScrollArea::vertical().id_salt("never_played").show(&mut columns[1], |ui| {
+                for file_path in &never_played {
+                    ui.label(sound_display_name(&app_state, file_path));
+                }
+            });
+
+            columns[2].heading("Plays per day");
+            egui::ScrollArea::vertical().id_salt("per_day").show(&mut columns[2], |ui| {
+                for (day, count) in &per_day {
+                    ui.label(format!("{} - {}", day, count));
+                }
+            });
+        });
+    });
+}
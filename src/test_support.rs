@@ -0,0 +1,42 @@
+// helpers shared by the test modules
+
+use std::{fs, path::PathBuf, sync::{Arc, Mutex}, thread, time::Duration};
+
+use crate::control::{ControlCommand, ControlRequest, ControlResponse, ListedSound};
+
+// an empty directory of its own for each test, left over runs of the same test are cleared first
+pub fn temp_dir(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("soundboard-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+// stands in for the update loop, answers with canned responses and returns the commands it was sent
+pub fn answer_requests(requests: Arc<Mutex<Vec<ControlRequest>>>) -> Arc<Mutex<Vec<ControlCommand>>> {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let receiving = Arc::clone(&received);
+
+    thread::spawn(move || loop {
+        for request in std::mem::take(&mut *requests.lock().unwrap()) {
+            let response = match &request.command {
+                ControlCommand::List { tab } => ControlResponse {
+                    sounds: Some(vec![ListedSound {
+                        tab: tab.clone().unwrap_or_else(|| "/sounds".to_string()),
+                        name: "airhorn.mp3".to_string(),
+                        path: "/sounds/airhorn.mp3".to_string(),
+                    }]),
+                    ..ControlResponse::ok()
+                },
+                ControlCommand::Play { sound } if sound != "airhorn" => ControlResponse::error(format!("No sound matches {}", sound)),
+                ControlCommand::Status => ControlResponse { playing: Some(Vec::new()), ..ControlResponse::ok() },
+                _ => ControlResponse::ok(),
+            };
+            receiving.lock().unwrap().push(request.command);
+            let _ = request.reply.send(response); // the client may have given up already
+        }
+        thread::sleep(Duration::from_millis(5));
+    });
+
+    received
+}
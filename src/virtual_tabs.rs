@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+use crate::{AppState, metadata::sound_id, save_data};
+
+pub const FAVORITES_TAB: &str = "Favorites";
+pub const RECENT_TAB: &str = "Recent";
+pub const VIRTUAL_TABS: [&str; 2] = [FAVORITES_TAB, RECENT_TAB];
+
+const RECENT_LIMIT: usize = 20;
+
+pub fn is_favorite(app_state: &AppState, file_path: &str) -> bool {
+    app_state.json_data.favorites.contains(&sound_id(app_state, file_path))
+}
+
+pub fn toggle_favorite(app_state: &mut AppState, file_path: &str) {
+    let sound_id = sound_id(app_state, file_path);
+
+    if let Some(index) = app_state.json_data.favorites.iter().position(|favorite| *favorite == sound_id) {
+        app_state.json_data.favorites.remove(index);
+    }
+    else {
+        app_state.json_data.favorites.push(sound_id);
+    }
+
+    save_data(app_state);
+}
+
+pub fn add_to_recent(app_state: &mut AppState, file_path: &str) {
+    let sound_id = sound_id(app_state, file_path);
+    if app_state.json_data.recent.first() == Some(&sound_id) {
+        return; // replaying the latest sound changes nothing, so data.json isn't rewritten
+    }
+
+    app_state.json_data.recent.retain(|recent| *recent != sound_id);
+    app_state.json_data.recent.insert(0, sound_id);
+    app_state.json_data.recent.truncate(RECENT_LIMIT);
+
+    save_data(app_state);
+}
+
+// sounds are stored by id, so look up where each one currently lives
+fn files_for_ids(app_state: &AppState, sound_ids: &[String]) -> Vec<String> {
+    let locations: HashMap<String, &String> = app_state
+        .loaded_files
+        .values()
+        .flatten()
+        .map(|file_path| (sound_id(app_state, file_path), file_path))
+        .collect();
+
+    sound_ids.iter().filter_map(|sound_id| locations.get(sound_id).map(|file_path| file_path.to_string())).collect()
+}
+
+pub fn tab_files(app_state: &AppState, tab: &str) -> Vec<String> {
+    if tab == FAVORITES_TAB {
+        files_for_ids(app_state, &app_state.json_data.favorites)
+    }
+    else if tab == RECENT_TAB {
+        files_for_ids(app_state, &app_state.json_data.recent)
+    }
+    else {
+        app_state.loaded_files.get(tab).cloned().unwrap_or_default()
+    }
+}
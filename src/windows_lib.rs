@@ -1,36 +1,39 @@
 use rodio::{
     OutputStream, OutputStreamBuilder,
-    cpal::{self, traits::{DeviceTrait, StreamTrait, HostTrait}, StreamConfig, SampleRate},
+    cpal::{self, traits::{DeviceTrait, StreamTrait, HostTrait}},
 };
+use crate::audio_format::{FormatConverter, pick_f32_config};
 use rfd::{MessageButtons, MessageDialog, MessageDialogResult};
 use ringbuf::{traits::*, HeapRb};
-use std::process;
+use std::{process, sync::mpsc, thread, time::{Duration, Instant}};
 
-fn route_standard_to_virtual(host: &cpal::Host, virtual_mic: &cpal::Device) {
-    let standard_mic = host.default_input_device().expect("Could not get default input device.");
+fn open_mic_route(virtual_mic: &cpal::Device, ring_buffer_msec: u32) -> Result<(cpal::Stream, cpal::Stream), String> {
+    let host = cpal::host_from_id(cpal::HostId::Wasapi).map_err(|err| format!("Could not initialize audio routing using WasAPI: {}", err))?;
+    let standard_mic = host.default_input_device().ok_or("Could not get default input device.")?;
 
-    let config = StreamConfig {
-        channels: 2,
-        sample_rate: SampleRate(48_000),
-        buffer_size: cpal::BufferSize::Default,
-    };
-    let rb = HeapRb::<f32>::new(48_000 * 2);
+    let input_configs = standard_mic.supported_input_configs().map_err(|err| format!("Could not list input configs: {}", err))?;
+    let input_config = pick_f32_config(input_configs).ok_or("Your microphone has no 32 bit float format to route.")?;
+    let output_configs = virtual_mic.supported_output_configs().map_err(|err| format!("Could not list VB Cable configs: {}", err))?;
+    let output_config = pick_f32_config(output_configs).ok_or("VB Cable has no 32 bit float format to route into.")?;
+    let mut converter = FormatConverter::new(input_config.channels(), input_config.sample_rate().0, output_config.channels(), output_config.sample_rate().0);
+
+    let buffer_size = output_config.sample_rate().0 as usize * output_config.channels() as usize * ring_buffer_msec as usize / 1000;
+    let rb = HeapRb::<f32>::new(buffer_size.max(1));
     let (mut producer, mut consumer) = rb.split();
 
     let input_stream = standard_mic.build_input_stream(
-        &config,
+        &input_config.config(),
         move |data: &[f32], _| {
-            for &sample in data {
-                let _ = producer.try_push(sample);
+            for sample in converter.process(data) {
                 let _ = producer.try_push(sample);
             }
         },
         move |err| eprintln!("Input stream error: {err}"),
         None,
-    ).expect("Could not build input stream for standard to virtual mic routing");
+    ).map_err(|err| format!("Could not build input stream for standard to virtual mic routing: {}", err))?;
 
     let output_stream = virtual_mic.build_output_stream(
-        &config,
+        &output_config.config(),
         move |data: &mut [f32], _| {
             for sample in data {
                 *sample = consumer.try_pop().unwrap_or(0.0);
@@ -38,13 +41,34 @@ fn route_standard_to_virtual(host: &cpal::Host, virtual_mic: &cpal::Device) {
         },
         move |err| eprintln!("Output stream error: {err}"),
         None,
-    ).expect("Could not build output stream for standard to virtual mic routing");
+    ).map_err(|err| format!("Could not build output stream for standard to virtual mic routing: {}", err))?;
 
-    input_stream.play();
-    output_stream.play();
+    input_stream.play().map_err(|err| format!("Could not start the microphone: {}", err))?;
+    output_stream.play().map_err(|err| format!("Could not start VB Cable: {}", err))?;
+    Ok((input_stream, output_stream))
 }
 
-pub fn create_virtual_mic_windows() -> (OutputStream, OutputStream) {
+// the mic is copied into VB Cable by cpal streams, which can't leave the thread they were made on, so they live on their own thread until the sender is dropped
+fn route_standard_to_virtual(virtual_mic: cpal::Device, ring_buffer_msec: u32) -> Result<mpsc::Sender<()>, String> {
+    let (stop_sender, stop_receiver) = mpsc::channel::<()>();
+    let (setup_sender, setup_receiver) = mpsc::channel::<Result<(), String>>();
+
+    thread::spawn(move || match open_mic_route(&virtual_mic, ring_buffer_msec) {
+        Ok(_streams) => {
+            let _ = setup_sender.send(Ok(()));
+            let _ = stop_receiver.recv(); // the streams stop once they are dropped
+        }
+        Err(err) => {
+            let _ = setup_sender.send(Err(err));
+        }
+    });
+
+    // wait for the setup, so a failure is reported instead of silently ending the thread
+    setup_receiver.recv().map_err(|_| "The mic routing thread stopped unexpectedly.".to_string())??;
+    Ok(stop_sender)
+}
+
+pub fn create_virtual_mic_windows(ring_buffer_msec: u32) -> (OutputStream, OutputStream, mpsc::Sender<()>) {
     let host = cpal::host_from_id(cpal::HostId::Wasapi)
         .expect("Could not initialize audio routing using WasAPI");
 
@@ -60,7 +84,14 @@ pub fn create_virtual_mic_windows() -> (OutputStream, OutputStream) {
         });
 
     if let Some(virtual_mic) = virtual_mic {        
-        route_standard_to_virtual(&host, &virtual_mic);
+        let mic_route = route_standard_to_virtual(virtual_mic.clone(), ring_buffer_msec).unwrap_or_else(|err| {
+            MessageDialog::new()
+                .set_title("Could not route your microphone into VB Cable.")
+                .set_description(format!("{} Sounds still play into VB Cable, but without your voice.", err))
+                .set_buttons(MessageButtons::Ok)
+                .show();
+            mpsc::channel().0
+        });
 
         let normal_output = host
             .default_output_device()
@@ -75,6 +106,7 @@ pub fn create_virtual_mic_windows() -> (OutputStream, OutputStream) {
                 .expect("Unable to open default audio device")
                 .open_stream()
                 .expect("Failed to open stream"),
+            mic_route,
         );
     }
     else {
@@ -86,4 +118,37 @@ pub fn create_virtual_mic_windows() -> (OutputStream, OutputStream) {
 
         std::process::exit(1);
     }
-}
\ No newline at end of file
+}
+
+// records what VB Cable hands to apps as their mic, used to measure latency
+pub fn capture_cable_output(on_chunk: &mut dyn FnMut(&[f32], Instant, u32) -> bool) -> Result<(), String> {
+    let host = cpal::host_from_id(cpal::HostId::Wasapi).map_err(|err| err.to_string())?;
+    let cable_output = host
+        .input_devices()
+        .map_err(|err| err.to_string())?
+        .find(|device| device.name().map(|name| name.contains("CABLE Output")).unwrap_or(false))
+        .ok_or("Could not find the VB Cable output device.")?;
+    let config = cable_output.default_input_config().map_err(|err| err.to_string())?;
+    let channels = config.channels() as usize;
+    let sample_rate = config.sample_rate().0;
+
+    let (sender, receiver) = mpsc::channel::<(Vec<f32>, Instant)>();
+    let stream = cable_output.build_input_stream(
+        &config.into(),
+        move |data: &[f32], _| {
+            // only the first channel is needed to find the click
+            let _ = sender.send((data.iter().step_by(channels).copied().collect(), Instant::now()));
+        },
+        move |err| eprintln!("Latency capture error: {err}"),
+        None,
+    ).map_err(|err| err.to_string())?;
+    stream.play().map_err(|err| err.to_string())?;
+
+    while let Ok((chunk, arrived)) = receiver.recv_timeout(Duration::from_secs(1)) {
+        if on_chunk(&chunk, arrived, sample_rate) {
+            break;
+        }
+    }
+
+    Ok(())
+}
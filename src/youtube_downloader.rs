@@ -0,0 +1,1376 @@
+use std::{fs, io::{BufRead, BufReader, Read}, path::{Path, PathBuf}, process::{Child, Command, Output, Stdio}, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}, thread, time::{Duration, Instant}};
+
+use bevy::prelude::ResMut;
+use bevy_egui::egui::{self, Color32, ColorImage, Context, TextureHandle};
+use rodio::{OutputStream, OutputStreamBuilder, Sink};
+
+use crate::{AppState, ALLOWED_FILE_EXTENSIONS, TRANSCODE_FILE_EXTENSIONS, import::{notify, unique_destination}, open_decoder, yt_dlp::yt_dlp_settings_ui};
+
+pub const BITRATES: [u32; 5] = [96, 128, 192, 256, 320];
+const LOOKUP_DELAY: Duration = Duration::from_millis(500); // how long typing has to pause before the url is looked up
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum JobState {
+    Queued,
+    Running,
+    Converting,
+    AwaitingReview,
+    Done,
+    Failed(String),
+    Cancelled,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Mp3,
+    Vorbis,
+    Flac,
+    Wav,
+    Opus, // not decoded by rodio, gets transcoded when the tab is loaded
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 5] = [OutputFormat::Mp3, OutputFormat::Vorbis, OutputFormat::Flac, OutputFormat::Wav, OutputFormat::Opus];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            OutputFormat::Mp3 => "MP3",
+            OutputFormat::Vorbis => "Ogg Vorbis",
+            OutputFormat::Flac => "FLAC",
+            OutputFormat::Wav => "WAV",
+            OutputFormat::Opus => "Opus (transcoded for playback)",
+        }
+    }
+
+    fn yt_dlp_name(self) -> &'static str { // value for --audio-format
+        match self {
+            OutputFormat::Mp3 => "mp3",
+            OutputFormat::Vorbis => "vorbis",
+            OutputFormat::Flac => "flac",
+            OutputFormat::Wav => "wav",
+            OutputFormat::Opus => "opus",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Vorbis => "ogg",
+            other => other.yt_dlp_name(),
+        }
+    }
+
+    pub fn is_lossless(self) -> bool {
+        matches!(self, OutputFormat::Flac | OutputFormat::Wav)
+    }
+
+    fn ffmpeg_codec(self) -> &'static str {
+        match self {
+            OutputFormat::Mp3 => "libmp3lame",
+            OutputFormat::Vorbis => "libvorbis",
+            OutputFormat::Flac => "flac",
+            OutputFormat::Wav => "pcm_s16le",
+            OutputFormat::Opus => "libopus",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct AudioSettings {
+    pub format: OutputFormat,
+    pub bitrate: u32, // kbit/s, ignored for lossless formats
+    pub normalize: bool,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings {
+            format: OutputFormat::Mp3,
+            bitrate: 192,
+            normalize: true,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct DownloadRequest {
+    pub url: String,
+    pub filename: String,
+    pub download_directory: String,
+    pub start_time: Option<f64>,
+    pub end_time: Option<f64>,
+    pub review: bool,
+    pub audio: AudioSettings,
+    pub overwrite: bool,
+}
+
+pub struct VideoInfo {
+    pub title: String,
+    pub duration: Option<f64>,
+    pub thumbnail_url: Option<String>,
+}
+
+pub struct BatchEntry {
+    pub url: String,
+    pub title: String,
+    pub duration: Option<f64>,
+    pub selected: bool,
+}
+
+pub enum LookupStatus {
+    Idle,
+    Loading,
+    Done(VideoInfo, Option<ColorImage>),
+    Batch(Vec<BatchEntry>, Vec<String>), // entries and the lines that could not be read
+    Failed(String),
+}
+
+// the yt-dlp process of one metadata lookup, killed when a newer lookup replaces it
+#[derive(Clone, Default)]
+pub struct LookupProcess {
+    child: Arc<Mutex<Option<Child>>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+pub struct DownloadJob {
+    pub id: u64,
+    pub request: DownloadRequest,
+    pub state: JobState,
+    pub progress: f32,
+    pub last_line: String,
+    pub attempts: u32,
+    pub review_file: Option<PathBuf>,
+    child: Arc<Mutex<Option<Child>>>,
+}
+
+pub struct DownloadQueue {
+    pub jobs: Arc<Mutex<Vec<DownloadJob>>>,
+    pub yt_dlp_path: String,
+    pub temp_directory: PathBuf,
+    pub max_parallel: usize,
+    pub library_changed: Arc<AtomicBool>,
+    next_id: u64,
+}
+
+pub struct YoutubeDownloaderState {
+    pub current_url: String,
+    pub current_filename: String,
+    pub download_directory: String,
+    pub start_time: String,
+    pub end_time: String,
+    pub review_before_adding: bool,
+    pub audio_settings: AudioSettings,
+    pub overwrite_existing: bool,
+    pub lookup: Arc<Mutex<LookupStatus>>, // replaced for every lookup, so late results of an old url are dropped
+    pub lookup_process: LookupProcess,
+    pub url_edited: Option<Instant>,
+    pub video_info: Option<VideoInfo>,
+    pub thumbnail: Option<TextureHandle>,
+    pub batch_entries: Vec<BatchEntry>,
+    pub auto_filename: String,
+    pub queue: DownloadQueue,
+    pub preview_stream: Option<OutputStream>,
+    pub preview_sink: Option<Sink>,
+}
+
+// yt-dlp prints "[download]  42.3% of 3.45MiB at 1.23MiB/s ETA 00:02" with --newline
+pub fn parse_progress(line: &str) -> Option<f32> {
+    let rest = line.strip_prefix("[download]")?.trim_start();
+    let percent = rest.split('%').next()?.trim();
+    percent.parse::<f32>().ok().map(|percent| (percent / 100.0).clamp(0.0, 1.0))
+}
+
+// accepts "90", "1:30" and "0:01:30.5"
+pub fn parse_timestamp(text: &str) -> Option<f64> {
+    let mut seconds = 0.0;
+    for part in text.trim().split(':') {
+        let value: f64 = part.trim().parse().ok()?;
+        if value < 0.0 {
+            return None;
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Some(seconds)
+}
+
+pub fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    }
+    else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+pub fn sanitize_filename(title: &str) -> String { // strips characters that are not allowed in file names on windows or unix
+    let cleaned: String = title
+        .chars()
+        .map(|char| if char.is_control() || "/\\:*?\"<>|".contains(char) { ' ' } else { char })
+        .collect();
+    let cleaned = cleaned.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    cleaned.chars().take(120).collect::<String>().trim_end_matches(['.', ' ']).to_string()
+}
+
+pub fn parse_video_info(json: &str) -> Result<VideoInfo, String> {
+    let value: serde_json::Value = serde_json::from_str(json).map_err(|err| format!("yt-dlp returned invalid metadata: {}", err))?;
+
+    // the main thumbnail is often webp, prefer the best jpg one which we can decode
+    let thumbnail_url = value["thumbnails"]
+        .as_array()
+        .and_then(|thumbnails| thumbnails.iter().rev().filter_map(|thumbnail| thumbnail["url"].as_str()).find(|url| url.split('?').next().unwrap_or_default().ends_with(".jpg")))
+        .or(value["thumbnail"].as_str())
+        .map(str::to_string);
+
+    Ok(VideoInfo {
+        title: value["title"].as_str().unwrap_or_default().to_string(),
+        duration: value["duration"].as_f64(),
+        thumbnail_url,
+    })
+}
+
+pub fn batch_urls(text: &str) -> Vec<String> {
+    text.lines().map(str::trim).filter(|line| line.starts_with("http://") || line.starts_with("https://")).map(str::to_string).collect()
+}
+
+// a watch url with a list parameter still means the single video
+fn is_playlist_url(url: &str) -> bool {
+    url.contains("/playlist") || (url.contains("list=") && !url.contains("v="))
+}
+
+pub fn is_batch_input(text: &str) -> bool {
+    let urls = batch_urls(text);
+    urls.len() > 1 || urls.first().is_some_and(|url| is_playlist_url(url))
+}
+
+// reads --flat-playlist -J output, which is either a playlist with entries or a single video
+pub fn parse_flat_playlist(json: &str) -> Result<Vec<BatchEntry>, String> {
+    let value: serde_json::Value = serde_json::from_str(json).map_err(|err| format!("yt-dlp returned invalid metadata: {}", err))?;
+
+    let entries = if value["_type"].as_str() == Some("playlist") {
+        value["entries"].as_array().cloned().unwrap_or_default()
+    }
+    else {
+        vec![value]
+    };
+
+    Ok(entries
+        .iter()
+        .filter_map(|entry| {
+            let url = entry["webpage_url"].as_str().or(entry["url"].as_str())?.to_string();
+            Some(BatchEntry {
+                title: entry["title"].as_str().map(str::to_string).unwrap_or(url.clone()),
+                url,
+                duration: entry["duration"].as_f64(),
+                selected: true,
+            })
+        })
+        .collect())
+}
+
+fn lookup_batch(process: &LookupProcess, yt_dlp_path: &str, urls: &[String]) -> LookupStatus {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+
+    for url in urls {
+        if process.is_cancelled() { // the text changed, the remaining urls are stale
+            return LookupStatus::Idle;
+        }
+
+        match process.output(Command::new(yt_dlp_path).args(["-J", "--flat-playlist", "--no-warnings", url])) {
+            Ok(output) if output.status.success() => match parse_flat_playlist(&String::from_utf8_lossy(&output.stdout)) {
+                Ok(found) => entries.extend(found),
+                Err(err) => errors.push(format!("{}: {}", url, err)),
+            },
+            Ok(output) => errors.push(format!("{}: {}", url, String::from_utf8_lossy(&output.stderr).lines().last().unwrap_or("yt-dlp failed"))),
+            Err(err) => errors.push(err),
+        }
+    }
+
+    LookupStatus::Batch(entries, errors)
+}
+
+pub fn start_batch_lookup(state: &mut YoutubeDownloaderState) {
+    let lookup = Arc::new(Mutex::new(LookupStatus::Loading));
+    state.lookup = Arc::clone(&lookup);
+    state.lookup_process.cancel();
+    state.lookup_process = LookupProcess::default();
+    state.video_info = None;
+    state.thumbnail = None;
+    state.batch_entries.clear();
+
+    let urls = batch_urls(&state.current_url);
+    let yt_dlp_path = state.queue.yt_dlp_path.clone();
+    let process = state.lookup_process.clone();
+    thread::spawn(move || {
+        let result = lookup_batch(&process, &yt_dlp_path, &urls);
+        *lookup.lock().expect("Metadata lookup lock poisoned") = result;
+    });
+}
+
+pub fn enqueue_batch(state: &mut YoutubeDownloaderState) -> usize {
+    let requests: Vec<DownloadRequest> = state
+        .batch_entries
+        .iter()
+        .filter(|entry| entry.selected)
+        .map(|entry| DownloadRequest {
+            url: entry.url.clone(),
+            filename: sanitize_filename(&entry.title),
+            download_directory: state.download_directory.clone(),
+            start_time: None,
+            end_time: None,
+            review: false,
+            audio: state.audio_settings,
+            overwrite: false,
+        })
+        .collect();
+
+    let count = requests.len();
+    for request in requests {
+        state.queue.enqueue(request);
+    }
+    count
+}
+
+fn fetch_thumbnail(url: &str) -> Option<ColorImage> {
+    let bytes = reqwest::blocking::get(url).and_then(|response| response.error_for_status()).and_then(|response| response.bytes()).ok()?;
+    let image = image::load_from_memory(&bytes).ok()?.to_rgba8();
+
+    Some(ColorImage::from_rgba_unmultiplied([image.width() as usize, image.height() as usize], image.as_raw()))
+}
+
+impl LookupProcess {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        if let Some(child) = self.child.lock().expect("Lookup process lock poisoned").as_mut() {
+            let _ = child.kill();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    // like Command::output, but the process can be killed from the ui thread while it runs
+    fn output(&self, command: &mut Command) -> Result<Output, String> {
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("Could not start yt-dlp: {}", err))?;
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        {
+            let mut slot = self.child.lock().expect("Lookup process lock poisoned");
+            if self.is_cancelled() { // cancelled before the process existed
+                let _ = child.kill();
+            }
+            *slot = Some(child);
+        }
+
+        let stderr_reader = stderr.map(|mut stderr| {
+            thread::spawn(move || {
+                let mut content = Vec::new();
+                let _ = stderr.read_to_end(&mut content);
+                content
+            })
+        });
+        let mut stdout_content = Vec::new();
+        if let Some(mut stdout) = stdout {
+            let _ = stdout.read_to_end(&mut stdout_content);
+        }
+        let stderr_content = stderr_reader.and_then(|reader| reader.join().ok()).unwrap_or_default();
+
+        let child = self.child.lock().expect("Lookup process lock poisoned").take();
+        let status = child
+            .ok_or("yt-dlp process disappeared")?
+            .wait()
+            .map_err(|err| format!("Could not wait for yt-dlp: {}", err))?;
+        if self.is_cancelled() {
+            return Err("Cancelled".to_string());
+        }
+
+        Ok(Output { status, stdout: stdout_content, stderr: stderr_content })
+    }
+}
+
+fn lookup_video(process: &LookupProcess, yt_dlp_path: &str, url: &str) -> Result<(VideoInfo, Option<ColorImage>), String> {
+    let output = process.output(Command::new(yt_dlp_path).args(["-J", "--no-playlist", "--no-warnings", url]))?;
+
+    if !output.status.success() {
+        let error_output = String::from_utf8_lossy(&output.stderr);
+        return Err(error_output.lines().last().unwrap_or("yt-dlp could not read the video").to_string());
+    }
+
+    let info = parse_video_info(&String::from_utf8_lossy(&output.stdout))?;
+    if process.is_cancelled() {
+        return Err("Cancelled".to_string());
+    }
+    let thumbnail = info.thumbnail_url.as_deref().and_then(fetch_thumbnail);
+    Ok((info, thumbnail))
+}
+
+pub fn start_lookup(state: &mut YoutubeDownloaderState) {
+    let lookup = Arc::new(Mutex::new(LookupStatus::Loading));
+    state.lookup = Arc::clone(&lookup);
+    state.lookup_process.cancel();
+    state.lookup_process = LookupProcess::default();
+    state.video_info = None;
+    state.thumbnail = None;
+    state.batch_entries.clear();
+
+    let url = state.current_url.trim().to_string();
+    let yt_dlp_path = state.queue.yt_dlp_path.clone();
+    let process = state.lookup_process.clone();
+    thread::spawn(move || {
+        let result = match lookup_video(&process, &yt_dlp_path, &url) {
+            Ok((info, thumbnail)) => LookupStatus::Done(info, thumbnail),
+            Err(err) => LookupStatus::Failed(err),
+        };
+        *lookup.lock().expect("Metadata lookup lock poisoned") = result;
+    });
+}
+
+fn poll_lookup(ctx: &Context, state: &mut YoutubeDownloaderState) {
+    let mut lookup = state.lookup.lock().expect("Metadata lookup lock poisoned");
+    if let LookupStatus::Batch(entries, errors) = &mut *lookup {
+        state.batch_entries = std::mem::take(entries);
+        if errors.is_empty() {
+            *lookup = LookupStatus::Idle;
+        }
+        else {
+            *lookup = LookupStatus::Failed(errors.join("\n")); // keep showing which lines failed
+        }
+        return;
+    }
+    if !matches!(*lookup, LookupStatus::Done(..)) {
+        return;
+    }
+
+    let LookupStatus::Done(info, thumbnail) = std::mem::replace(&mut *lookup, LookupStatus::Idle) else {
+        return;
+    };
+    drop(lookup);
+
+    // only replace the filename if the user hasn't typed their own
+    if state.current_filename.trim().is_empty() || state.current_filename == state.auto_filename {
+        state.auto_filename = sanitize_filename(&info.title);
+        state.current_filename = state.auto_filename.clone();
+    }
+    state.thumbnail = thumbnail.map(|image| ctx.load_texture("youtube_thumbnail", image, Default::default()));
+    state.video_info = Some(info);
+}
+
+fn update_job(jobs: &Arc<Mutex<Vec<DownloadJob>>>, id: u64, update: impl FnOnce(&mut DownloadJob)) {
+    if let Some(job) = jobs.lock().expect("Download queue lock poisoned").iter_mut().find(|job| job.id == id) {
+        update(job);
+    }
+}
+
+fn job_state(jobs: &Arc<Mutex<Vec<DownloadJob>>>, id: u64) -> Option<JobState> {
+    jobs.lock().expect("Download queue lock poisoned").iter().find(|job| job.id == id).map(|job| job.state.clone())
+}
+
+impl DownloadQueue {
+    pub fn new(yt_dlp_path: String, temp_directory: PathBuf) -> Self {
+        DownloadQueue {
+            jobs: Arc::new(Mutex::new(Vec::new())),
+            yt_dlp_path,
+            temp_directory,
+            max_parallel: 2,
+            library_changed: Arc::new(AtomicBool::new(false)),
+            next_id: 0,
+        }
+    }
+
+    pub fn enqueue(&mut self, request: DownloadRequest) -> u64 {
+        self.next_id += 1;
+        self.jobs.lock().expect("Download queue lock poisoned").push(DownloadJob {
+            id: self.next_id,
+            request,
+            state: JobState::Queued,
+            progress: 0.0,
+            last_line: String::new(),
+            attempts: 0,
+            review_file: None,
+            child: Arc::new(Mutex::new(None)),
+        });
+        self.next_id
+    }
+
+    pub fn process(&self) { // starts queued jobs while there are free slots, call this every frame
+        let mut jobs = self.jobs.lock().expect("Download queue lock poisoned");
+        let active = jobs.iter().filter(|job| matches!(job.state, JobState::Running | JobState::Converting)).count();
+        let free_slots = self.max_parallel.saturating_sub(active);
+
+        for job in jobs.iter_mut().filter(|job| job.state == JobState::Queued).take(free_slots) {
+            job.state = JobState::Running;
+            job.progress = 0.0;
+            job.attempts += 1;
+
+            let jobs = Arc::clone(&self.jobs);
+            let library_changed = Arc::clone(&self.library_changed);
+            let child = Arc::clone(&job.child);
+            let yt_dlp_path = self.yt_dlp_path.clone();
+            let temp_directory = self.temp_directory.clone();
+            let (id, request) = (job.id, job.request.clone());
+
+            thread::spawn(move || {
+                let result = run_job(&jobs, id, &child, &yt_dlp_path, &temp_directory, &request);
+
+                // a cancelled job keeps its state, killing yt-dlp makes it look like a failure otherwise
+                if job_state(&jobs, id) == Some(JobState::Cancelled) {
+                    return;
+                }
+
+                update_job(&jobs, id, |job| match result {
+                    Ok(Some(review_file)) => {
+                        job.state = JobState::AwaitingReview;
+                        job.progress = 1.0;
+                        job.last_line.clear(); // reused for a failed commit
+                        job.review_file = Some(review_file);
+                    }
+                    Ok(None) => {
+                        job.state = JobState::Done;
+                        job.progress = 1.0;
+                    }
+                    Err(err) => job.state = JobState::Failed(err),
+                });
+
+                if job_state(&jobs, id) == Some(JobState::Done) {
+                    library_changed.store(true, Ordering::SeqCst);
+                }
+            });
+        }
+    }
+
+    pub fn cancel(&self, id: u64) {
+        let mut jobs = self.jobs.lock().expect("Download queue lock poisoned");
+        if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+            job.state = JobState::Cancelled;
+            if let Some(child) = job.child.lock().expect("Download process lock poisoned").as_mut() {
+                let _ = child.kill();
+            }
+        }
+    }
+
+    pub fn retry(&self, id: u64) {
+        update_job(&self.jobs, id, |job| {
+            if matches!(job.state, JobState::Failed(_) | JobState::Cancelled) {
+                job.state = JobState::Queued;
+                job.progress = 0.0;
+            }
+        });
+    }
+
+    pub fn commit(&self, id: u64) { // moves a reviewed download into its tab
+        let Some((review_file, request)) = self
+            .jobs
+            .lock()
+            .expect("Download queue lock poisoned")
+            .iter()
+            .find(|job| job.id == id && job.state == JobState::AwaitingReview)
+            .and_then(|job| Some((job.review_file.clone()?, job.request.clone())))
+        else {
+            return;
+        };
+
+        // the file stays with the job until it is moved, so a failed commit can be retried or discarded
+        match move_into_tab(&review_file, &request, id) {
+            Ok(()) => {
+                update_job(&self.jobs, id, |job| {
+                    job.review_file = None;
+                    job.state = JobState::Done;
+                });
+                self.library_changed.store(true, Ordering::SeqCst);
+            }
+            Err(err) => update_job(&self.jobs, id, |job| job.last_line = err),
+        }
+    }
+
+    pub fn discard(&self, id: u64) {
+        update_job(&self.jobs, id, |job| {
+            if let Some(review_file) = job.review_file.take() {
+                let _ = fs::remove_file(review_file);
+            }
+            job.state = JobState::Cancelled;
+        });
+    }
+
+    pub fn remove(&self, id: u64) {
+        self.jobs.lock().expect("Download queue lock poisoned").retain(|job| job.id != id || matches!(job.state, JobState::Queued | JobState::Running | JobState::Converting));
+    }
+}
+
+// the extension always follows the chosen format, a typed audio extension is replaced instead of doubled
+pub fn output_filename(filename: &str, format: OutputFormat, id: u64) -> String {
+    let filename = filename.trim();
+    let path = Path::new(filename);
+    let has_audio_extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .is_some_and(|extension| ALLOWED_FILE_EXTENSIONS.contains(&extension.as_str()) || TRANSCODE_FILE_EXTENSIONS.contains(&extension.as_str()));
+
+    let stem = if has_audio_extension { path.file_stem().unwrap_or_default().to_string_lossy().to_string() } else { filename.to_string() };
+    let stem = if stem.is_empty() { format!("download-{}", id) } else { stem };
+
+    format!("{}.{}", stem, format.extension())
+}
+
+fn normalize_loudness(file: &Path, audio: AudioSettings) -> Result<(), String> {
+    let extension = file.extension().unwrap_or_default().to_string_lossy();
+    let partial = file.with_extension(format!("normalized.{}", extension));
+
+    let mut command = Command::new("ffmpeg");
+    command
+        .args(["-y", "-loglevel", "error", "-i"])
+        .arg(file)
+        .args(["-vn", "-af", "loudnorm=I=-16:TP=-1.5:LRA=11", "-c:a", audio.format.ffmpeg_codec()]);
+    if !audio.format.is_lossless() {
+        command.args(["-b:a", &format!("{}k", audio.bitrate)]);
+    }
+
+    let output = command.arg(&partial).output().map_err(|err| format!("Could not start FFmpeg for normalization: {}", err))?;
+    if !output.status.success() {
+        let _ = fs::remove_file(&partial);
+        let reason = String::from_utf8_lossy(&output.stderr).lines().last().unwrap_or("FFmpeg failed").to_string();
+        return Err(format!("Normalization failed: {}", reason));
+    }
+
+    fs::rename(&partial, file).map_err(|err| format!("Could not replace {}: {}", file.display(), err))
+}
+
+fn move_into_tab(downloaded: &Path, request: &DownloadRequest, id: u64) -> Result<(), String> {
+    let filename = output_filename(&request.filename, request.audio.format, id);
+    let destination = if request.overwrite {
+        let destination = Path::new(&request.download_directory).join(&filename);
+        if destination.exists() {
+            fs::remove_file(&destination).map_err(|err| format!("Could not overwrite {}: {}", destination.display(), err))?;
+        }
+        destination
+    }
+    else {
+        unique_destination(Path::new(&request.download_directory), &filename)
+    };
+
+    // rename fails across filesystems, fall back to copying
+    if fs::rename(downloaded, &destination).is_err() {
+        fs::copy(downloaded, &destination).map_err(|err| format!("Could not move the download to {}: {}", destination.display(), err))?;
+        let _ = fs::remove_file(downloaded);
+    }
+
+    Ok(())
+}
+
+// returns the downloaded file if it still has to be reviewed before going into the tab
+fn run_job(jobs: &Arc<Mutex<Vec<DownloadJob>>>, id: u64, child_slot: &Arc<Mutex<Option<Child>>>, yt_dlp_path: &str, temp_directory: &Path, request: &DownloadRequest) -> Result<Option<PathBuf>, String> {
+    fs::create_dir_all(temp_directory).map_err(|err| format!("Could not create {}: {}", temp_directory.display(), err))?;
+
+    // every job gets its own temp file, so parallel downloads can't overwrite each other
+    let prefix = format!("job-{}-{}", std::process::id(), id);
+    let output_template = temp_directory.join(format!("{}.%(ext)s", prefix));
+
+    let mut command = Command::new(yt_dlp_path);
+    command.args(["-x", "--audio-format", request.audio.format.yt_dlp_name(), "--newline", "-o"]).arg(&output_template);
+    if !request.audio.format.is_lossless() {
+        command.args(["--audio-quality", &format!("{}K", request.audio.bitrate)]);
+    }
+
+    if request.start_time.is_some() || request.end_time.is_some() {
+        let start = request.start_time.unwrap_or(0.0);
+        let end = request.end_time.map(|end| end.to_string()).unwrap_or("inf".to_string());
+        command.args(["--download-sections", &format!("*{}-{}", start, end), "--force-keyframes-at-cuts"]);
+    }
+
+    let mut child = command
+        .arg(&request.url)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("Could not start yt-dlp: {}", err))?;
+
+    let stdout = child.stdout.take();
+    // read stderr on its own thread, a full pipe would block yt-dlp while we wait on stdout
+    let stderr_reader = child.stderr.take().map(|stderr| {
+        thread::spawn(move || BufReader::new(stderr).lines().map_while(Result::ok).collect::<Vec<_>>().join("\n"))
+    });
+    *child_slot.lock().expect("Download process lock poisoned") = Some(child);
+
+    if job_state(jobs, id) == Some(JobState::Cancelled) { // cancelled before the process existed
+        if let Some(child) = child_slot.lock().expect("Download process lock poisoned").as_mut() {
+            let _ = child.kill();
+        }
+    }
+
+    if let Some(stdout) = stdout {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            update_job(jobs, id, |job| {
+                if let Some(progress) = parse_progress(&line) {
+                    job.progress = progress;
+                }
+                if line.starts_with("[ExtractAudio]") && job.state == JobState::Running {
+                    job.state = JobState::Converting;
+                }
+                job.last_line = line.clone();
+            });
+        }
+    }
+
+    let error_output = stderr_reader.and_then(|reader| reader.join().ok()).unwrap_or_default();
+
+    let child = child_slot.lock().expect("Download process lock poisoned").take(); // don't hold the lock while waiting, cancel needs it
+    let status = child
+        .ok_or("yt-dlp process disappeared")?
+        .wait()
+        .map_err(|err| format!("Could not wait for yt-dlp: {}", err))?;
+
+    let downloaded = fs::read_dir(temp_directory)
+        .map_err(|err| format!("Could not read {}: {}", temp_directory.display(), err))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .find(|path| path.file_stem().is_some_and(|stem| stem.to_string_lossy() == prefix));
+
+    if !status.success() {
+        if let Some(downloaded) = downloaded {
+            let _ = fs::remove_file(downloaded);
+        }
+        let reason = error_output.lines().last().unwrap_or("yt-dlp failed").to_string();
+        return Err(reason);
+    }
+
+    let downloaded = downloaded.ok_or("yt-dlp did not produce an output file")?;
+
+    if request.audio.normalize {
+        update_job(jobs, id, |job| job.state = JobState::Converting);
+        if let Err(err) = normalize_loudness(&downloaded, request.audio) {
+            let _ = fs::remove_file(&downloaded);
+            return Err(err);
+        }
+    }
+
+    // killing yt-dlp no longer helps once it is post-processing, so a late cancel is honoured here
+    if job_state(jobs, id) == Some(JobState::Cancelled) {
+        let _ = fs::remove_file(&downloaded);
+        return Err("Cancelled".to_string());
+    }
+
+    if request.review {
+        return Ok(Some(downloaded));
+    }
+
+    move_into_tab(&downloaded, request, id)?;
+    Ok(None)
+}
+
+fn preview(state: &mut YoutubeDownloaderState, file: &Path) -> Result<(), String> {
+    // previews go to the default output device only, so nobody else hears clips that get discarded
+    if state.preview_stream.is_none() {
+        let mut stream = OutputStreamBuilder::open_default_stream().map_err(|err| format!("Could not open audio device for preview: {}", err))?;
+        stream.log_on_drop(false);
+        state.preview_stream = Some(stream);
+    }
+
+    let src = open_decoder(&file.to_string_lossy())?;
+    let sink = Sink::connect_new(state.preview_stream.as_ref().expect("Preview stream was just opened").mixer());
+    sink.append(src);
+    state.preview_sink = Some(sink); // replacing the sink stops the previous preview
+    Ok(())
+}
+
+pub fn youtube_downloader_ui(ctx: &Context, mut app_state: ResMut<AppState>) {
+    poll_lookup(ctx, &mut app_state.youtube_downloader_state);
+
+    egui::CentralPanel::default().show(ctx, |ui| {
+        let available_width = ui.available_width();
+        let available_height = ui.available_height();
+
+        yt_dlp_settings_ui(ui, &mut app_state);
+
+        ui.with_layout(egui::Layout::top_down(egui::Align::Center), |ui| {
+            ui.heading("Directory");
+            egui::ComboBox::from_id_salt("Download Directory Selector")
+                .selected_text(app_state.youtube_downloader_state.download_directory.clone())
+                .width(available_width)
+                .height(available_height / 15.0)
+                .show_ui(ui, |ui| {
+                    for directory in &app_state.loaded_files.keys().cloned().collect::<Vec<_>>() {
+                        ui.selectable_value(
+                            &mut app_state.youtube_downloader_state.download_directory,
+                            directory.clone(),
+                            directory,
+                        );
+                    }
+                });
+
+            ui.heading("Youtube URL");
+            let url_response = ui.add(
+                egui::TextEdit::multiline(&mut app_state.youtube_downloader_state.current_url)
+                    .desired_width(available_width)
+                    .desired_rows(2)
+                    .hint_text("a video or playlist URL, or one URL per line"),
+            );
+            if url_response.changed() {
+                app_state.youtube_downloader_state.url_edited = Some(Instant::now());
+            }
+
+            // yt-dlp only runs once typing pauses or the field is left, not for every keystroke
+            let state = &mut app_state.youtube_downloader_state;
+            if let Some(edited) = state.url_edited {
+                if edited.elapsed() >= LOOKUP_DELAY || url_response.lost_focus() {
+                    state.url_edited = None;
+                    let url = state.current_url.trim();
+                    if is_batch_input(url) {
+                        start_batch_lookup(state);
+                    }
+                    else {
+                        state.batch_entries.clear(); // entries of an earlier url list would still be downloaded otherwise
+                        if url.starts_with("http://") || url.starts_with("https://") {
+                            start_lookup(state);
+                        }
+                        else {
+                            state.lookup_process.cancel();
+                            state.lookup = Arc::new(Mutex::new(LookupStatus::Idle)); // drops a late result of the old text
+                        }
+                    }
+                }
+                else {
+                    ctx.request_repaint_after(LOOKUP_DELAY - edited.elapsed()); // egui only redraws on input otherwise
+                }
+            }
+
+            let state = &app_state.youtube_downloader_state;
+            match &*state.lookup.lock().expect("Metadata lookup lock poisoned") {
+                LookupStatus::Loading => {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label("Looking up video...");
+                    });
+                }
+                LookupStatus::Failed(err) => {
+                    ui.colored_label(Color32::RED, err);
+                }
+                LookupStatus::Idle | LookupStatus::Done(..) | LookupStatus::Batch(..) => {}
+            }
+
+            if let Some(info) = &state.video_info {
+                ui.horizontal(|ui| {
+                    if let Some(thumbnail) = &state.thumbnail {
+                        let height = available_height / 8.0;
+                        let width = height * thumbnail.aspect_ratio();
+                        ui.image((thumbnail.id(), egui::vec2(width, height)));
+                    }
+                    ui.vertical(|ui| {
+                        ui.strong(&info.title);
+                        if let Some(duration) = info.duration {
+                            ui.label(format_duration(duration));
+                        }
+                    });
+                });
+            }
+
+            let state = &mut app_state.youtube_downloader_state;
+            if !state.batch_entries.is_empty() {
+                ui.horizontal(|ui| {
+                    let selected = state.batch_entries.iter().filter(|entry| entry.selected).count();
+                    ui.heading(format!("Entries ({} of {} selected)", selected, state.batch_entries.len()));
+                    if ui.button("Select all").clicked() {
+                        state.batch_entries.iter_mut().for_each(|entry| entry.selected = true);
+                    }
+                    if ui.button("Select none").clicked() {
+                        state.batch_entries.iter_mut().for_each(|entry| entry.selected = false);
+                    }
+                });
+
+                egui::ScrollArea::vertical().id_salt("batch_entries").max_height(available_height / 4.0).show(ui, |ui| {
+                    for entry in &mut state.batch_entries {
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut entry.selected, &entry.title);
+                            if let Some(duration) = entry.duration {
+                                ui.label(format_duration(duration));
+                            }
+                        });
+                    }
+                });
+            }
+            else {
+                ui.heading("Filename");
+                ui.add_sized([available_width, available_height / 20.0], egui::TextEdit::singleline(&mut state.current_filename).hint_text("the extension follows the chosen format"));
+
+                if !state.current_filename.trim().is_empty() && !state.download_directory.is_empty() {
+                    let filename = output_filename(&state.current_filename, state.audio_settings.format, 0);
+                    if Path::new(&state.download_directory).join(&filename).exists() {
+                        ui.horizontal(|ui| {
+                            ui.colored_label(Color32::YELLOW, format!("{} already exists.", filename));
+                            ui.radio_value(&mut state.overwrite_existing, false, "Keep both");
+                            ui.radio_value(&mut state.overwrite_existing, true, "Overwrite");
+                        });
+                    }
+                }
+
+                ui.heading("Clip (optional)");
+                ui.horizontal(|ui| {
+                    let field_width = available_width / 4.0;
+                    ui.label("Start");
+                    ui.add(egui::TextEdit::singleline(&mut state.start_time).hint_text("0:00").desired_width(field_width));
+                    ui.label("End");
+                    ui.add(egui::TextEdit::singleline(&mut state.end_time).hint_text("end").desired_width(field_width));
+                    ui.checkbox(&mut state.review_before_adding, "Preview before adding to tab");
+                });
+            }
+
+            ui.heading("Format");
+            ui.horizontal(|ui| {
+                let audio = &mut app_state.youtube_downloader_state.audio_settings;
+                egui::ComboBox::from_id_salt("Download Format Selector")
+                    .selected_text(audio.format.label())
+                    .show_ui(ui, |ui| {
+                        for format in OutputFormat::ALL {
+                            ui.selectable_value(&mut audio.format, format, format.label());
+                        }
+                    });
+
+                if !audio.format.is_lossless() {
+                    egui::ComboBox::from_id_salt("Download Bitrate Selector")
+                        .selected_text(format!("{} kbit/s", audio.bitrate))
+                        .show_ui(ui, |ui| {
+                            for bitrate in BITRATES {
+                                ui.selectable_value(&mut audio.bitrate, bitrate, format!("{} kbit/s", bitrate));
+                            }
+                        });
+                }
+
+                ui.checkbox(&mut audio.normalize, "Normalize loudness");
+            });
+        });
+
+        if ui
+            .add_sized(
+                [available_width, available_height / 15.0],
+                egui::Button::new("Add to download queue"),
+            )
+            .clicked()
+        {
+            let state = &mut app_state.youtube_downloader_state;
+            if !state.batch_entries.is_empty() {
+                if state.download_directory.is_empty() {
+                    notify(&mut app_state, "Select a directory first.".to_string());
+                }
+                else {
+                    let count = enqueue_batch(state);
+                    notify(&mut app_state, format!("Added {} download(s) to the queue.", count));
+                }
+            }
+            else {
+                let start_time = state.start_time.trim();
+                let end_time = state.end_time.trim();
+                let parsed_start = if start_time.is_empty() { Some(None) } else { parse_timestamp(start_time).map(Some) };
+                let parsed_end = if end_time.is_empty() { Some(None) } else { parse_timestamp(end_time).map(Some) };
+
+                let filename = output_filename(&state.current_filename, state.audio_settings.format, 0);
+                let exists = !state.current_filename.trim().is_empty() && Path::new(&state.download_directory).join(filename).exists();
+
+                match (parsed_start, parsed_end) {
+                    _ if state.current_url.trim().is_empty() || state.download_directory.is_empty() => {
+                        notify(&mut app_state, "Enter a URL and select a directory first.".to_string());
+                    }
+                    (Some(Some(start)), Some(Some(end))) if end <= start => {
+                        notify(&mut app_state, "The clip end has to be after its start.".to_string());
+                    }
+                    (Some(start_time), Some(end_time)) => {
+                        let request = DownloadRequest {
+                            url: state.current_url.trim().to_string(),
+                            filename: state.current_filename.clone(),
+                            download_directory: state.download_directory.clone(),
+                            start_time,
+                            end_time,
+                            review: state.review_before_adding,
+                            audio: state.audio_settings,
+                            overwrite: exists && state.overwrite_existing,
+                        };
+                        state.queue.enqueue(request);
+                    }
+                    _ => notify(&mut app_state, "Clip times have to look like 90, 1:30 or 0:01:30.5".to_string()),
+                }
+            }
+        };
+
+        ui.separator();
+        ui.heading("Downloads");
+
+        let mut cancelled = Vec::new();
+        let mut retried = Vec::new();
+        let mut removed = Vec::new();
+        let mut previewed = None;
+        let mut committed = Vec::new();
+        let mut discarded = Vec::new();
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            let jobs = app_state.youtube_downloader_state.queue.jobs.lock().expect("Download queue lock poisoned");
+            if jobs.is_empty() {
+                ui.label("The download queue is empty.");
+            }
+
+            for job in jobs.iter() {
+                ui.horizontal(|ui| {
+                    let (state_text, color) = match &job.state {
+                        JobState::Queued => ("Queued".to_string(), Color32::GRAY),
+                        JobState::Running => ("Downloading".to_string(), Color32::LIGHT_BLUE),
+                        JobState::Converting => ("Converting".to_string(), Color32::LIGHT_BLUE),
+                        JobState::AwaitingReview => ("Ready for preview".to_string(), Color32::LIGHT_GREEN),
+                        JobState::Done => ("Done".to_string(), Color32::GREEN),
+                        JobState::Failed(err) => (format!("Failed: {}", err), Color32::RED),
+                        JobState::Cancelled => ("Cancelled".to_string(), Color32::YELLOW),
+                    };
+                    ui.label(if job.request.filename.is_empty() { job.request.url.clone() } else { output_filename(&job.request.filename, job.request.audio.format, job.id) });
+                    ui.colored_label(color, state_text);
+                    if job.attempts > 1 {
+                        ui.label(format!("(attempt {})", job.attempts));
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.add(egui::ProgressBar::new(job.progress).show_percentage().desired_width(ui.available_width() * 0.7));
+
+                    match job.state {
+                        JobState::Queued | JobState::Running | JobState::Converting => {
+                            if ui.button("Cancel").clicked() {
+                                cancelled.push(job.id);
+                            }
+                        }
+                        JobState::AwaitingReview => {
+                            if ui.button("Preview").clicked() {
+                                previewed = job.review_file.clone();
+                            }
+                            if ui.button("Add to tab").clicked() {
+                                committed.push(job.id);
+                            }
+                            if ui.button("Discard").clicked() {
+                                discarded.push(job.id);
+                            }
+                        }
+                        JobState::Failed(_) | JobState::Cancelled => {
+                            if ui.button("Retry").clicked() {
+                                retried.push(job.id);
+                            }
+                            if ui.button("Remove").clicked() {
+                                removed.push(job.id);
+                            }
+                        }
+                        JobState::Done => {
+                            if ui.button("Remove").clicked() {
+                                removed.push(job.id);
+                            }
+                        }
+                    }
+                });
+
+                if !job.last_line.is_empty() && matches!(job.state, JobState::Running | JobState::Converting | JobState::AwaitingReview) {
+                    ui.small(&job.last_line);
+                }
+                ui.separator();
+            }
+        });
+
+        if !committed.is_empty() || !discarded.is_empty() {
+            app_state.youtube_downloader_state.preview_sink = None;
+        }
+
+        let queue = &app_state.youtube_downloader_state.queue;
+        cancelled.into_iter().for_each(|id| queue.cancel(id));
+        retried.into_iter().for_each(|id| queue.retry(id));
+        removed.into_iter().for_each(|id| queue.remove(id));
+        committed.into_iter().for_each(|id| queue.commit(id));
+        discarded.into_iter().for_each(|id| queue.discard(id));
+
+        if let Some(file) = previewed && let Err(err) = preview(&mut app_state.youtube_downloader_state, &file) {
+            notify(&mut app_state, err);
+        }
+    });
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use std::{os::unix::fs::PermissionsExt, time::{Duration, Instant}};
+
+    // stands in for yt-dlp: prints progress like the real one and writes the -o file
+    fn stub_yt_dlp(directory: &Path, body: &str) -> String {
+        let script = directory.join("yt-dlp-stub");
+        fs::write(&script, format!(
+            "#!/bin/sh\next=mp3\nwhile [ $# -gt 0 ]; do case \"$1\" in -o) out=\"$2\"; shift;; --audio-format) ext=\"$2\"; shift;; esac; shift; done\n[ \"$ext\" = vorbis ] && ext=ogg\nout=$(echo \"$out\" | sed \"s/%(ext)s/$ext/\")\n{}\n",
+            body
+        )).unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        script.to_string_lossy().to_string()
+    }
+
+    fn request(url: &str, filename: &str, download_directory: String) -> DownloadRequest {
+        DownloadRequest { url: url.to_string(), filename: filename.to_string(), download_directory, start_time: None, end_time: None, review: false, audio: AudioSettings { normalize: false, ..AudioSettings::default() }, overwrite: false }
+    }
+
+    fn wait_for(queue: &DownloadQueue, id: u64, done: impl Fn(&JobState) -> bool) -> JobState {
+        let started = Instant::now();
+        loop {
+            let state = job_state(&queue.jobs, id).unwrap();
+            if done(&state) || started.elapsed() > Duration::from_secs(10) {
+                return state;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn parses_yt_dlp_progress_lines() {
+        assert_eq!(parse_progress("[download]  42.5% of    3.45MiB at    1.23MiB/s ETA 00:02"), Some(0.425));
+        assert_eq!(parse_progress("[download] 100% of 3.45MiB in 00:00:02"), Some(1.0));
+        assert_eq!(parse_progress("[download] Destination: sound.webm"), None);
+        assert_eq!(parse_progress("[ExtractAudio] Destination: sound.mp3"), None);
+    }
+
+    #[test]
+    fn parses_clip_timestamps() {
+        assert_eq!(parse_timestamp("90"), Some(90.0));
+        assert_eq!(parse_timestamp("1:30"), Some(90.0));
+        assert_eq!(parse_timestamp("0:01:30.5"), Some(90.5));
+        assert_eq!(parse_timestamp("1:xx"), None);
+        assert_eq!(parse_timestamp("-5"), None);
+    }
+
+    #[test]
+    fn output_filename_follows_the_format() {
+        assert_eq!(output_filename("airhorn", OutputFormat::Flac, 1), "airhorn.flac");
+        assert_eq!(output_filename("airhorn.mp3", OutputFormat::Vorbis, 1), "airhorn.ogg");
+        assert_eq!(output_filename("mr. bean", OutputFormat::Opus, 1), "mr. bean.opus");
+        assert_eq!(output_filename("  ", OutputFormat::Wav, 7), "download-7.wav");
+    }
+
+    #[test]
+    fn sanitizes_titles_into_filenames() {
+        assert_eq!(sanitize_filename("AC/DC - Back In Black (Official Video)"), "AC DC - Back In Black (Official Video)");
+        assert_eq!(sanitize_filename("what?  \"really\"..."), "what really");
+        assert_eq!(sanitize_filename("a\nb:c"), "a b c");
+        assert_eq!(sanitize_filename(&"x".repeat(300)).len(), 120);
+    }
+
+    #[test]
+    fn reads_yt_dlp_metadata() {
+        let info = parse_video_info(r#"{"title": "Bruh", "duration": 3725.0, "thumbnail": "https://i.ytimg.com/vi/x/maxresdefault.webp",
+            "thumbnails": [{"url": "https://i.ytimg.com/vi/x/default.jpg"}, {"url": "https://i.ytimg.com/vi/x/hqdefault.jpg?sqp=1"}, {"url": "https://i.ytimg.com/vi/x/maxresdefault.webp"}]}"#).unwrap();
+
+        assert_eq!(info.title, "Bruh");
+        assert_eq!(info.duration.map(format_duration), Some("1:02:05".to_string()));
+        assert_eq!(info.thumbnail_url.as_deref(), Some("https://i.ytimg.com/vi/x/hqdefault.jpg?sqp=1"));
+        assert!(parse_video_info("not json").is_err());
+    }
+
+    #[test]
+    fn detects_batch_input() {
+        assert!(!is_batch_input("https://www.youtube.com/watch?v=abc"));
+        assert!(!is_batch_input("https://www.youtube.com/watch?v=abc&list=PL1"));
+        assert!(is_batch_input("https://www.youtube.com/playlist?list=PL1"));
+        assert!(is_batch_input("https://youtu.be/a\n\n  https://youtu.be/b  \n"));
+        assert_eq!(batch_urls("https://youtu.be/a\nnot a url\nhttps://youtu.be/b"), vec!["https://youtu.be/a", "https://youtu.be/b"]);
+    }
+
+    #[test]
+    fn reads_flat_playlists() {
+        let entries = parse_flat_playlist(r#"{"_type": "playlist", "title": "memes", "entries": [
+            {"_type": "url", "url": "https://www.youtube.com/watch?v=a", "title": "Bruh", "duration": 2.0},
+            {"_type": "url", "url": "https://www.youtube.com/watch?v=b", "title": null},
+            {"_type": "url", "title": "no url"}]}"#).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].title, "Bruh");
+        assert_eq!(entries[0].duration, Some(2.0));
+        assert_eq!(entries[1].title, "https://www.youtube.com/watch?v=b");
+        assert!(entries.iter().all(|entry| entry.selected));
+
+        let single = parse_flat_playlist(r#"{"title": "Video", "webpage_url": "https://www.youtube.com/watch?v=c", "url": "https://cdn.example/stream"}"#).unwrap();
+        assert_eq!(single[0].url, "https://www.youtube.com/watch?v=c");
+    }
+
+    #[test]
+    fn overwrites_existing_files_when_asked() {
+        let directory = temp_dir("overwrite");
+        fs::write(directory.join("a.mp3"), "old").unwrap();
+        let stub = stub_yt_dlp(&directory, "printf 'ID3' > \"$out\"");
+
+        let mut queue = DownloadQueue::new(stub, directory.join("temp"));
+        queue.max_parallel = 1; // the second job has to see the overwritten file
+        let mut overwrite = request("https://example.com/a", "a", directory.to_string_lossy().to_string());
+        overwrite.overwrite = true;
+        let id = queue.enqueue(overwrite);
+        let renamed = queue.enqueue(request("https://example.com/a", "a", directory.to_string_lossy().to_string()));
+        queue.process();
+
+        assert_eq!(wait_for(&queue, id, |state| *state == JobState::Done), JobState::Done);
+        queue.process();
+        assert_eq!(wait_for(&queue, renamed, |state| *state == JobState::Done), JobState::Done);
+        assert_eq!(fs::read_to_string(directory.join("a.mp3")).unwrap(), "ID3");
+        assert!(directory.join("a (1).mp3").exists());
+    }
+
+    #[test]
+    fn downloads_in_the_chosen_format() {
+        let directory = temp_dir("format");
+        let stub = stub_yt_dlp(&directory, "printf 'fLaC' > \"$out\"");
+
+        let mut queue = DownloadQueue::new(stub, directory.join("temp"));
+        let mut flac = request("https://example.com/a", "a", directory.to_string_lossy().to_string());
+        flac.audio.format = OutputFormat::Flac;
+        let id = queue.enqueue(flac);
+        queue.process();
+
+        assert_eq!(wait_for(&queue, id, |state| *state == JobState::Done), JobState::Done);
+        assert!(directory.join("a.flac").exists());
+    }
+
+    #[test]
+    fn reviewed_downloads_wait_for_commit() {
+        let directory = temp_dir("review");
+        let arguments = directory.join("arguments");
+        let stub = stub_yt_dlp(&directory, &format!("echo \"$ALL\" > '{}'\nprintf 'ID3' > \"$out\"", arguments.display()));
+        let stub_script = fs::read_to_string(&stub).unwrap().replacen("#!/bin/sh\n", "#!/bin/sh\nALL=\"$*\"\n", 1);
+        fs::write(&stub, stub_script).unwrap();
+
+        let mut queue = DownloadQueue::new(stub, directory.join("temp"));
+        let mut clip = request("https://example.com/a", "a.mp3", directory.to_string_lossy().to_string());
+        clip.start_time = Some(1.5);
+        clip.end_time = Some(4.0);
+        clip.review = true;
+        let id = queue.enqueue(clip);
+        queue.process();
+
+        assert_eq!(wait_for(&queue, id, |state| *state == JobState::AwaitingReview), JobState::AwaitingReview);
+        assert!(fs::read_to_string(&arguments).unwrap().contains("--download-sections *1.5-4"));
+        assert!(!directory.join("a.mp3").exists());
+
+        queue.commit(id);
+        assert_eq!(job_state(&queue.jobs, id), Some(JobState::Done));
+        assert!(directory.join("a.mp3").exists());
+    }
+
+    #[test]
+    fn failed_commits_keep_the_reviewed_file() {
+        let directory = temp_dir("review-failed");
+        let stub = stub_yt_dlp(&directory, "printf 'ID3' > \"$out\"");
+
+        let mut queue = DownloadQueue::new(stub, directory.join("temp"));
+        let mut clip = request("https://example.com/a", "a.mp3", directory.join("missing").to_string_lossy().to_string());
+        clip.review = true;
+        let id = queue.enqueue(clip);
+        queue.process();
+        assert_eq!(wait_for(&queue, id, |state| *state == JobState::AwaitingReview), JobState::AwaitingReview);
+
+        queue.commit(id);
+        let review_file = queue.jobs.lock().unwrap()[0].review_file.clone();
+        assert_eq!(job_state(&queue.jobs, id), Some(JobState::AwaitingReview));
+        assert!(review_file.is_some_and(|review_file| review_file.exists()));
+        assert!(!queue.library_changed.load(Ordering::SeqCst));
+
+        queue.discard(id);
+        assert_eq!(fs::read_dir(directory.join("temp")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn downloads_into_the_target_directory() {
+        let directory = temp_dir("download");
+        let stub = stub_yt_dlp(&directory, "echo '[download]  50.0% of 1.00MiB'\necho '[ExtractAudio] Destination: x'\nprintf 'ID3' > \"$out\"");
+        let target = directory.join("tab");
+        fs::create_dir_all(&target).unwrap();
+
+        let mut queue = DownloadQueue::new(stub, directory.join("temp"));
+        let first = queue.enqueue(request("https://example.com/a", "a.mp3", target.to_string_lossy().to_string()));
+        let second = queue.enqueue(request("https://example.com/b", "b.mp3", target.to_string_lossy().to_string()));
+        queue.process();
+
+        assert_eq!(wait_for(&queue, first, |state| *state == JobState::Done), JobState::Done);
+        assert_eq!(wait_for(&queue, second, |state| *state == JobState::Done), JobState::Done);
+        assert!(target.join("a.mp3").exists());
+        assert!(target.join("b.mp3").exists());
+        assert!(queue.library_changed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn failed_jobs_can_be_retried() {
+        let directory = temp_dir("retry");
+        let marker = directory.join("failed-once");
+        let stub = stub_yt_dlp(&directory, &format!(
+            "if [ ! -e '{0}' ]; then touch '{0}'; echo 'ERROR: network down' >&2; exit 1; fi\nprintf 'ID3' > \"$out\"",
+            marker.display()
+        ));
+
+        let mut queue = DownloadQueue::new(stub, directory.join("temp"));
+        let id = queue.enqueue(request("https://example.com/a", "a.mp3", directory.to_string_lossy().to_string()));
+        queue.process();
+        assert_eq!(wait_for(&queue, id, |state| matches!(state, JobState::Failed(_))), JobState::Failed("ERROR: network down".to_string()));
+
+        queue.retry(id);
+        queue.process();
+        assert_eq!(wait_for(&queue, id, |state| *state == JobState::Done), JobState::Done);
+    }
+
+    #[test]
+    fn cancelling_kills_the_process() {
+        let directory = temp_dir("cancel");
+        let stub = stub_yt_dlp(&directory, "echo '[download]   1.0% of 1.00MiB'\nexec sleep 30");
+
+        let mut queue = DownloadQueue::new(stub, directory.join("temp"));
+        let id = queue.enqueue(request("https://example.com/a", "a.mp3", directory.to_string_lossy().to_string()));
+        queue.process();
+        wait_for(&queue, id, |_| queue.jobs.lock().unwrap()[0].progress > 0.0);
+
+        let started = Instant::now();
+        queue.cancel(id);
+        while queue.jobs.lock().unwrap()[0].child.lock().unwrap().is_some() && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(job_state(&queue.jobs, id), Some(JobState::Cancelled));
+        assert!(!directory.join("a.mp3").exists());
+    }
+
+    #[test]
+    fn cancelling_while_post_processing_keeps_the_file_out_of_the_tab() {
+        let directory = temp_dir("cancel-late");
+        // the stub exits right away but a child keeps stdout open, like yt-dlp handing off to ffmpeg
+        let stub = stub_yt_dlp(&directory, "printf 'ID3' > \"$out\"\necho '[ExtractAudio] Destination: x'\nsleep 1 &");
+        let temp = directory.join("temp");
+
+        let mut queue = DownloadQueue::new(stub, temp.clone());
+        let id = queue.enqueue(request("https://example.com/a", "a.mp3", directory.to_string_lossy().to_string()));
+        queue.process();
+        assert_eq!(wait_for(&queue, id, |state| *state == JobState::Converting), JobState::Converting);
+        queue.cancel(id);
+
+        let started = Instant::now();
+        while fs::read_dir(&temp).unwrap().next().is_some() && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        assert!(fs::read_dir(&temp).unwrap().next().is_none());
+        assert!(!directory.join("a.mp3").exists());
+        assert_eq!(job_state(&queue.jobs, id), Some(JobState::Cancelled));
+    }
+
+    #[test]
+    fn cancelled_lookups_kill_yt_dlp() {
+        let directory = temp_dir("lookup-cancel");
+        let stub = stub_yt_dlp(&directory, "exec sleep 30");
+
+        let process = LookupProcess::default();
+        let lookup = {
+            let process = process.clone();
+            thread::spawn(move || lookup_video(&process, &stub, "https://example.com/a").map(|(info, _)| info.title))
+        };
+        let started = Instant::now();
+        while process.child.lock().unwrap().is_none() && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        process.cancel();
+        assert_eq!(lookup.join().unwrap(), Err("Cancelled".to_string()));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
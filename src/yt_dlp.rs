@@ -1,82 +1,281 @@
-use std::{env::current_dir, fs::{File, exists}, io, process::Command};
+use std::{env::current_dir, fs, path::Path, process::Command, sync::{Arc, Mutex}, thread};
 use reqwest;
-use rfd::{MessageButtons, MessageDialog, MessageDialogResult};
+use bevy_egui::egui::{self, Color32, Ui};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{AppState, save_data};
 
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
-pub fn get_yt_dlp_path() -> String {
-    if cfg!(target_os = "windows"){
-        current_dir().expect("Failed to get current working directory").join("bin").join("yt-dlp.exe").to_string_lossy().to_string()
-    }
-    else if cfg!(target_os = "macos"){
-        current_dir().expect("Failed to get current working directory").join("bin").join("yt-dlp_macos").to_string_lossy().to_string()
-    }
-    else if cfg!(target_os = "linux"){
-        current_dir().expect("Failed to get current working directory").join("bin").join("yt-dlp_linux").to_string_lossy().to_string()
-    }
-    else {
-        "".to_string()
+pub const DEFAULT_RELEASE_URL: &str = "https://github.com/yt-dlp/yt-dlp/releases/latest/download";
+const CHECKSUM_FILE: &str = "SHA2-256SUMS";
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct YtDlpSettings {
+    pub use_system: bool,
+    pub system_path: String,
+    pub release_url: String, // where the binary and SHA2-256SUMS are downloaded from
+    pub auto_update: bool,
+}
+
+impl Default for YtDlpSettings {
+    fn default() -> Self {
+        YtDlpSettings {
+            use_system: false,
+            system_path: "yt-dlp".to_string(),
+            release_url: DEFAULT_RELEASE_URL.to_string(),
+            auto_update: true,
+        }
     }
 }
 
-pub fn check_and_download_yt_dlp() {
-    let url: &str;
+#[derive(Clone, PartialEq, Debug)]
+pub enum YtDlpStatus {
+    Unknown,
+    Checking,
+    Ready(String), // installed version
+    Updated(String),
+    Failed(String),
+}
 
+fn yt_dlp_binary_name() -> Option<&'static str> {
     if cfg!(target_os = "windows"){
-        url = "https://github.com/yt-dlp/yt-dlp/releases/latest/download/yt-dlp.exe";
+        Some("yt-dlp.exe")
     }
     else if cfg!(target_os = "macos"){
-        url = "https://github.com/yt-dlp/yt-dlp/releases/latest/download/yt-dlp_macos";
+        Some("yt-dlp_macos")
     }
     else if cfg!(target_os = "linux"){
-        url = "https://github.com/yt-dlp/yt-dlp/releases/latest/download/yt-dlp_linux";   
+        Some("yt-dlp_linux")
     }
     else {
-        return;
+        None
     }
+}
 
-    if exists(get_yt_dlp_path()).expect("Could not check existence of yt dlp executable.") {
-        return;
+pub fn get_yt_dlp_path() -> String {
+    match yt_dlp_binary_name() {
+        Some(name) => current_dir().expect("Failed to get current working directory").join("bin").join(name).to_string_lossy().to_string(),
+        None => "".to_string(),
     }
-    
-    let mut body = reqwest::blocking::get(url).expect("Could not download yt-dlp");
-    let mut out = File::create(get_yt_dlp_path()).expect("failed to create file");
-    io::copy(&mut body, &mut out).expect("failed to copy content");
+}
 
+pub fn configured_yt_dlp_path(settings: &YtDlpSettings) -> String {
+    if settings.use_system { settings.system_path.trim().to_string() } else { get_yt_dlp_path() }
+}
+
+pub fn yt_dlp_version(path: &str) -> Option<String> {
+    let output = Command::new(path).arg("--version").output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// lines look like "<sha256>  yt-dlp_linux", sha256sum marks binary mode with a * before the name
+pub fn parse_checksums(checksums: &str, name: &str) -> Option<String> {
+    checksums.lines().find_map(|line| {
+        let mut parts = line.split_whitespace();
+        let hash = parts.next()?;
+        (parts.next()?.trim_start_matches('*') == name).then(|| hash.to_lowercase())
+    })
+}
+
+fn download(url: &str) -> Result<Vec<u8>, String> {
+    reqwest::blocking::get(url)
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.bytes())
+        .map(|bytes| bytes.to_vec())
+        .map_err(|err| format!("Could not download {}: {}", url, err))
+}
+
+pub fn update_yt_dlp(release_url: &str, destination: &Path) -> Result<YtDlpStatus, String> {
+    let name = yt_dlp_binary_name().ok_or("yt-dlp has no release for this platform")?;
+    let release_url = release_url.trim().trim_end_matches('/');
+
+    let checksums = String::from_utf8_lossy(&download(&format!("{}/{}", release_url, CHECKSUM_FILE))?).to_string();
+    let expected = parse_checksums(&checksums, name).ok_or(format!("{} has no entry for {}", CHECKSUM_FILE, name))?;
+
+    let installed = fs::read(destination).ok().map(|content| format!("{:x}", Sha256::digest(&content)));
+    if installed.as_deref() == Some(expected.as_str()) {
+        let version = yt_dlp_version(&destination.to_string_lossy()).ok_or("The installed yt-dlp does not run")?;
+        return Ok(YtDlpStatus::Ready(version));
+    }
+
+    let binary = download(&format!("{}/{}", release_url, name))?;
+    let actual = format!("{:x}", Sha256::digest(&binary));
+    if actual != expected {
+        return Err(format!("Checksum mismatch for {}: expected {}, got {}", name, expected, actual));
+    }
+
+    // write next to the old binary and swap, so a running download never sees half a file
+    let partial = destination.with_extension("part");
+    fs::write(&partial, &binary).map_err(|err| format!("Could not write {}: {}", partial.display(), err))?;
     #[cfg(unix)]
-    out.set_permissions(PermissionsExt::from_mode(0o755));
+    fs::set_permissions(&partial, PermissionsExt::from_mode(0o755)).map_err(|err| format!("Could not make yt-dlp executable: {}", err))?;
+    if destination.exists() {
+        let _ = fs::remove_file(destination); // windows can't rename over an existing file
+    }
+    fs::rename(&partial, destination).map_err(|err| format!("Could not install yt-dlp: {}", err))?;
 
+    let version = yt_dlp_version(&destination.to_string_lossy()).ok_or("The downloaded yt-dlp does not run")?;
+    Ok(YtDlpStatus::Updated(version))
+}
+
+pub fn start_yt_dlp_update(settings: &YtDlpSettings, status: &Arc<Mutex<YtDlpStatus>>) {
+    *status.lock().expect("yt-dlp status lock poisoned") = YtDlpStatus::Checking;
+
+    let settings = settings.clone();
+    let status = Arc::clone(status);
+    thread::spawn(move || {
+        let result = if settings.use_system {
+            // a system install is updated by its package manager, only check that it works
+            match yt_dlp_version(&settings.system_path) {
+                Some(version) => YtDlpStatus::Ready(version),
+                None => YtDlpStatus::Failed(format!("Could not run {}", settings.system_path)),
+            }
+        }
+        else {
+            update_yt_dlp(&settings.release_url, Path::new(&get_yt_dlp_path())).unwrap_or_else(YtDlpStatus::Failed)
+        };
+        *status.lock().expect("yt-dlp status lock poisoned") = result;
+    });
+}
+
+pub fn yt_dlp_settings_ui(ui: &mut Ui, app_state: &mut AppState) {
+    egui::CollapsingHeader::new("yt-dlp").id_salt("yt_dlp_settings").show(ui, |ui| {
+        let status = app_state.yt_dlp_status.lock().expect("yt-dlp status lock poisoned").clone();
+        match &status {
+            YtDlpStatus::Unknown => ui.label("Not checked yet"),
+            YtDlpStatus::Checking => ui.label("Checking for updates..."),
+            YtDlpStatus::Ready(version) => ui.label(format!("Version {} (up to date)", version)),
+            YtDlpStatus::Updated(version) => ui.colored_label(Color32::GREEN, format!("Updated to version {}", version)),
+            YtDlpStatus::Failed(err) => ui.colored_label(Color32::RED, err),
+        };
+
+        let mut settings = app_state.json_data.yt_dlp.clone();
+        ui.checkbox(&mut settings.use_system, "Use the yt-dlp installed on this system");
+        ui.horizontal(|ui| {
+            if settings.use_system {
+                ui.label("Path");
+                ui.text_edit_singleline(&mut settings.system_path);
+            }
+            else {
+                ui.label("Download from");
+                ui.text_edit_singleline(&mut settings.release_url);
+                if ui.button("Reset").clicked() {
+                    settings.release_url = DEFAULT_RELEASE_URL.to_string();
+                }
+            }
+        });
+        if !settings.use_system {
+            ui.checkbox(&mut settings.auto_update, "Check for updates on startup");
+        }
+
+        if settings != app_state.json_data.yt_dlp {
+            app_state.youtube_downloader_state.queue.yt_dlp_path = configured_yt_dlp_path(&settings);
+            app_state.json_data.yt_dlp = settings.clone();
+            save_data(app_state);
+        }
+
+        let label = if settings.use_system { "Check now" } else { "Update now" };
+        if ui.add_enabled(status != YtDlpStatus::Checking, egui::Button::new(label)).clicked() {
+            start_yt_dlp_update(&settings, &app_state.yt_dlp_status);
+        }
+    });
 }
 
 pub fn check_ffmpeg() -> bool{
     return std::process::Command::new("ffmpeg").output().is_ok();
 }
 
-pub fn check_and_download_ffmpeg() {
-    if check_ffmpeg() {
-        return;
+pub const FFMPEG_INSTALL_HINT: &str = "Install FFmpeg and the libavcodec shared libraries from your package manager and make sure ffmpeg is in PATH.";
+
+pub fn install_ffmpeg() -> Result<(), String> {
+    if !cfg!(target_os = "windows") {
+        return Err(FFMPEG_INSTALL_HINT.to_string());
     }
 
-    if cfg!(target_os = "windows"){
-        let confirmed = MessageDialog::new()
-            .set_title("FFmpeg Download Optional.")
-            .set_description("The youtube downloader depends on FFmpeg for mp3 conversion. This app can auto-install FFmpeg with winget. Do you want to install FFmpeg?")
-            .set_buttons(MessageButtons::YesNo)
-            .show();
-
-        if confirmed == MessageDialogResult::Ok {
-            Command::new("winget")
-                .args(&["install", "BtbN.FFmpeg.GPL.Shared.8.0", "--source winget", "--accept-source-agreements", "--accept-package-agreements"]) // as_str is needed here as you cannot instantly dereference a growing String (Rust...)
-                .output()
-                .expect("Failed to execute process");
-        }
+    let output = Command::new("winget")
+        .args(["install", "BtbN.FFmpeg.GPL.Shared.8.0", "--source", "winget", "--accept-source-agreements", "--accept-package-agreements"])
+        .output()
+        .map_err(|err| format!("Could not run winget: {}", err))?;
+
+    if output.status.success() { Ok(()) } else { Err(String::from_utf8_lossy(&output.stdout).lines().last().unwrap_or("winget failed").to_string()) }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use std::{collections::HashMap, io::{Read, Write}, net::TcpListener};
+
+    // stands in for the github release, answers every request from the given files and 404s otherwise
+    fn serve(files: HashMap<String, Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            for mut stream in listener.incoming().map_while(Result::ok) {
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    let read = stream.read(&mut buffer).unwrap_or(0);
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..read]);
+                }
+
+                let request = String::from_utf8_lossy(&request);
+                let path = request.split_whitespace().nth(1).unwrap_or_default().trim_start_matches('/');
+                let response = match files.get(path) {
+                    Some(body) => [format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()).into_bytes(), body.clone()].concat(),
+                    None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec(),
+                };
+                let _ = stream.write_all(&response);
+            }
+        });
+
+        format!("http://{}/release", address)
     }
-    else {
-        MessageDialog::new()
-            .set_title("FFmpeg Download Optional.")
-            .set_description("The youtube downloader depends on FFmpeg for mp3 conversion. You are on a Linux or Darwin based OS. If you want to use the Youtube Downloader, you need to install FFmpeg and libavcodec shared libraries from your package manager to make sure it is in PATH.")
-            .set_buttons(MessageButtons::Ok)
-            .show();
+
+    fn release(binary: &[u8], checksum: &str) -> HashMap<String, Vec<u8>> {
+        let name = yt_dlp_binary_name().unwrap();
+        HashMap::from([
+            (format!("release/{}", name), binary.to_vec()),
+            (format!("release/{}", CHECKSUM_FILE), format!("0000  yt-dlp.exe\n{}  {}\n", checksum, name).into_bytes()),
+        ])
+    }
+
+    #[test]
+    fn parses_checksum_files() {
+        let checksums = "abc  yt-dlp\nDEF *yt-dlp_linux\n\n";
+        assert_eq!(parse_checksums(checksums, "yt-dlp_linux"), Some("def".to_string()));
+        assert_eq!(parse_checksums(checksums, "yt-dlp"), Some("abc".to_string()));
+        assert_eq!(parse_checksums(checksums, "yt-dlp_macos"), None);
+    }
+
+    #[test]
+    fn installs_verified_release_once() {
+        let directory = temp_dir("install");
+        let destination = directory.join("yt-dlp");
+        let binary = b"#!/bin/sh\necho 2099.01.01\n";
+        let url = serve(release(binary, &format!("{:x}", Sha256::digest(binary))));
+
+        assert_eq!(update_yt_dlp(&url, &destination), Ok(YtDlpStatus::Updated("2099.01.01".to_string())));
+        assert_eq!(update_yt_dlp(&url, &destination), Ok(YtDlpStatus::Ready("2099.01.01".to_string())));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn rejects_binaries_with_wrong_checksum() {
+        let directory = temp_dir("mismatch");
+        let destination = directory.join("yt-dlp");
+        fs::write(&destination, "old").unwrap();
+        let url = serve(release(b"#!/bin/sh\necho evil\n", &"0".repeat(64)));
+
+        assert!(update_yt_dlp(&url, &destination).is_err_and(|err| err.starts_with("Checksum mismatch")));
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "old");
+        assert!(!destination.with_extension("part").exists());
+    }
+}